@@ -5,6 +5,7 @@ use cosmwasm_std::{
 use crate::{
     execute,
     msg::{ExecuteMsg, InstantiateMsg, QueryMsg},
+    query,
     state::{AdminStore, ContractStore, InvoiceStore},
 };
 
@@ -24,37 +25,87 @@ pub fn instantiate(
 #[entry_point]
 pub fn execute(deps: DepsMut, env: Env, info: MessageInfo, msg: ExecuteMsg) -> StdResult<Response> {
     match msg {
-        ExecuteMsg::SubmitInvoice {
-            purpose,
-            amount,
-            admin_charge,
-            customer_charge,
-            payer,
-            days,
-            recurrent_time,
-            token,
-        } => execute::new_invoice(
-            deps,
-            env,
-            info,
-            purpose,
-            amount,
-            admin_charge,
-            customer_charge,
-            payer,
-            days,
-            recurrent_time,
-            token,
-        ),
+        ExecuteMsg::SubmitInvoice(msg) => execute::new_invoice(deps, env, info, msg),
+        ExecuteMsg::SetFundingRate { id, token, rate } => {
+            execute::set_funding_rate(deps, info, id, token, rate)
+        }
+        ExecuteMsg::AgreeFundingRate { id, token, rate } => {
+            execute::agree_funding_rate(deps, info, id, token, rate)
+        }
         ExecuteMsg::AcceptInvoice { id } => execute::accept_invoice(deps, env, info, id),
+        ExecuteMsg::TopUp { id } => execute::top_up(deps, env, info, id),
+        ExecuteMsg::SubmitMilestone { id, index, proof } => {
+            execute::submit_milestone(deps, env, info, id, index, proof)
+        }
+        ExecuteMsg::ApproveMilestone { id, index } => {
+            execute::approve_milestone(deps, env, info, id, index)
+        }
         ExecuteMsg::CancelPayment { id } => execute::stop_contract(deps, env, info, id),
+        ExecuteMsg::TerminateWithNotice { id } => {
+            execute::terminate_with_notice(deps, env, info, id)
+        }
+        ExecuteMsg::Resign { id } => execute::resign(deps, env, info, id),
+        ExecuteMsg::ProposeAmendment {
+            id,
+            new_amount,
+            new_period,
+            new_count,
+        } => execute::propose_amendment(deps, env, info, id, new_amount, new_period, new_count),
+        ExecuteMsg::AcceptAmendment { id } => execute::accept_amendment(deps, env, info, id),
         ExecuteMsg::WithdrawPayment { id } => execute::withdraw_payment(deps, env, info, id),
-        ExecuteMsg::AdminUpdateAmin { newAdmin } => execute::admin_change_admin(deps, env, info, newAdmin)
+        ExecuteMsg::SetPayoutAddress { id, payout_address } => {
+            execute::set_payout_address(deps, info, id, payout_address)
+        }
+        ExecuteMsg::SetPayoutSplit { id, split } => {
+            execute::set_payout_split(deps, info, id, split)
+        }
+        ExecuteMsg::RaiseDispute { id, reason } => {
+            execute::raise_dispute(deps, env, info, id, reason)
+        }
+        ExecuteMsg::ResolveDispute {
+            id,
+            payee_share,
+            payer_share,
+        } => execute::resolve_dispute(deps, env, info, id, payee_share, payer_share),
+        ExecuteMsg::SetArbiter { arbiter } => execute::set_arbiter(deps, info, arbiter),
+        ExecuteMsg::AdminUpdateAmin { newAdmin } => {
+            execute::admin_change_admin(deps, env, info, newAdmin)
+        }
+        ExecuteMsg::PurgeExpired { limit } => execute::purge_expired(deps, env, limit),
+        ExecuteMsg::DepositTreasury {} => execute::deposit_treasury(deps, env, info),
+        ExecuteMsg::WithdrawTreasury { amount } => {
+            execute::withdraw_treasury(deps, env, info, amount)
+        }
+        ExecuteMsg::GrantOperator {
+            operator,
+            permissions,
+            expires,
+        } => execute::grant_operator(deps, env, info, operator, permissions, expires),
+        ExecuteMsg::RevokeOperator { operator } => execute::revoke_operator(deps, info, operator),
+        ExecuteMsg::CreateOrganization { name } => execute::create_organization(deps, info, name),
+        ExecuteMsg::AddOrganizationMember { id, member, admin } => {
+            execute::add_organization_member(deps, info, id, member, admin)
+        }
+        ExecuteMsg::RemoveOrganizationMember { id, member } => {
+            execute::remove_organization_member(deps, info, id, member)
+        }
+        ExecuteMsg::SetTags { id, tags } => execute::set_tags(deps, info, id, tags),
+        ExecuteMsg::SetViewingKey { key } => execute::set_viewing_key(deps, info, key),
+        ExecuteMsg::RevokePermit { permit_name } => {
+            execute::revoke_permit(deps, info, permit_name)
+        }
+        ExecuteMsg::SetMemo { id, memo } => execute::set_memo(deps, info, id, memo),
+        ExecuteMsg::SetWithholding { id, withholding } => {
+            execute::set_withholding(deps, info, id, withholding)
+        }
+        ExecuteMsg::AgreeWithholding { id, withholding } => {
+            execute::agree_withholding(deps, info, id, withholding)
+        }
     }
 }
 
 #[entry_point]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::SingleInvoice { id, owner } => {
             to_binary(&InvoiceStore::load_invoice(deps.storage, &owner, id))
@@ -90,7 +141,102 @@ pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
         )?),
         QueryMsg::AdmimWallet {  } => to_binary(
             &AdminStore::get_admin_wallet( deps.storage)
-        )
+        ),
+        QueryMsg::FilteredInvoice {
+            owner,
+            filter,
+            sort_by,
+            descending,
+            start_after,
+            limit,
+        } => to_binary(&query::filtered_invoices(
+            deps,
+            env,
+            owner,
+            filter,
+            sort_by,
+            descending,
+            start_after,
+            limit,
+        )?),
+        QueryMsg::FilteredContract {
+            payer,
+            filter,
+            sort_by,
+            descending,
+            start_after,
+            limit,
+        } => to_binary(&query::filtered_contracts(
+            deps,
+            env,
+            payer,
+            filter,
+            sort_by,
+            descending,
+            start_after,
+            limit,
+        )?),
+        QueryMsg::PayerObligations { payer, from, to } => {
+            to_binary(&query::payer_obligations(deps, payer, from, to)?)
+        }
+        QueryMsg::PayeeStatement {
+            payee,
+            from,
+            to,
+            token,
+            page,
+            auth,
+        } => to_binary(&query::payee_statement(
+            deps, env, payee, from, to, token, page, auth,
+        )?),
+        QueryMsg::InvoiceHistory {
+            id,
+            address,
+            page,
+            page_size,
+            auth,
+        } => to_binary(&query::invoice_history(
+            deps, env, id, address, page, page_size, auth,
+        )?),
+        QueryMsg::FundingStatus { id, payer } => {
+            to_binary(&query::funding_status(deps, env, id, payer)?)
+        }
+        QueryMsg::Treasury { payer } => to_binary(&query::treasury(deps, payer)?),
+        QueryMsg::Dispute { id, address, auth } => {
+            to_binary(&query::dispute(deps, env, id, address, auth)?)
+        }
+        QueryMsg::Organization { id, address, auth } => {
+            to_binary(&query::organization(deps, env, id, address, auth)?)
+        }
+        QueryMsg::OrganizationContracts {
+            id,
+            address,
+            page,
+            page_size,
+            auth,
+        } => to_binary(&query::organization_contracts(
+            deps, env, id, address, page, page_size, auth,
+        )?),
+        QueryMsg::Tags { id, payer, auth } => to_binary(&query::tags(deps, env, id, payer, auth)?),
+        QueryMsg::SpendByTag {
+            payer,
+            tag,
+            from,
+            to,
+            auth,
+        } => to_binary(&query::spend_by_tag(deps, env, payer, tag, from, to, auth)?),
+        QueryMsg::PayrollReport {
+            payer,
+            from,
+            to,
+            page,
+            auth,
+        } => to_binary(&query::payroll_report(
+            deps, env, payer, from, to, page, auth,
+        )?),
+        QueryMsg::Memo { id, address, auth } => {
+            to_binary(&query::memo(deps, env, id, address, auth)?)
+        }
     }
 }
 
@@ -98,10 +244,24 @@ pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
 mod tests {
     use super::*;
     use cosmwasm_std::testing::*;
-    use cosmwasm_std::{from_binary, Coin, Uint128};
+    use cosmwasm_std::{
+        coins, from_binary, from_slice, Addr, BankMsg, Coin, CosmosMsg, Decimal, Uint128,
+    };
+    use secret_toolkit::permit::RevokedPermits;
     use secret_toolkit::utils::types::Token;
 
-    use crate::state::Contract;
+    use crate::msg::{
+        ContractListResponse, DisputeResponse, FundingStatusResponse, InvoiceHistoryResponse,
+        InvoiceListResponse, ListingCursor, ListingFilter, MemoResponse,
+        OrganizationContractsResponse, PayeeStatementResponse, PayerObligationsResponse,
+        PayrollReportResponse, QueryAuth, SpendByTagResponse, SubmitInvoiceMsg, TreasuryResponse,
+    };
+    use crate::state::{
+        CancellationPolicy, Contract, EarlyPaymentTerms, HistoryStore, InvoiceEventKind,
+        InvoiceStatus, LateFee, LatePaymentTerms, LineItem, MilestonePlan, MilestoneTerms,
+        OperatorPermission, PayoutShare, PayrollRowKind, SortBy, Tag, Withholding, CONTRACT,
+        INVOICE, PREFIX_REVOKED_PERMITS,
+    };
 
     #[test]
     fn submit_invoice() {
@@ -131,17 +291,29 @@ mod tests {
             }],
         );
 
-        let exec_msg = ExecuteMsg::SubmitInvoice {
+        let exec_msg = ExecuteMsg::SubmitInvoice(SubmitInvoiceMsg {
             purpose: "building".to_string(),
             //amount: Uint128::new(3),
             amount: 3,
             admin_charge: Uint128::new(3),
             customer_charge: Uint128::new(3),
-            payer: "secret1py4ryg3atyz5cru2m64p0mtga5y09q5a26pa7n".to_string(),
+            payer: Some("secret1py4ryg3atyz5cru2m64p0mtga5y09q5a26pa7n".to_string()),
             days: 2,
             recurrent_time: Some(2),
             token: Token::Native("uscrt".to_string()),
-        };
+            late_payment: None,
+            early_payment: None,
+            milestones: None,
+            arbiter: None,
+            cancellation_policy: None,
+            expires_at: None,
+            valid_for_days: None,
+            payout_address: None,
+            funding_tokens: None,
+            organization: None,
+            line_items: None,
+            memo: None,
+        });
 
         let _res = execute(deps.as_mut(), mock_env(), info, exec_msg).unwrap();
 
@@ -191,17 +363,29 @@ mod tests {
             }],
         );
 
-        let exec_msg = ExecuteMsg::SubmitInvoice {
+        let exec_msg = ExecuteMsg::SubmitInvoice(SubmitInvoiceMsg {
             purpose: "building".to_string(),
             //amount: Uint128::new(3),
             amount: 3,
             admin_charge: Uint128::new(3),
             customer_charge: Uint128::new(3),
-            payer: "secret1py4ryg3atyz5cru2m64p0mtga5y09q5a26pa7n".to_string(),
+            payer: Some("secret1py4ryg3atyz5cru2m64p0mtga5y09q5a26pa7n".to_string()),
             days: 2,
             recurrent_time: Some(2),
             token: Token::Native("uscrt".to_string()),
-        };
+            late_payment: None,
+            early_payment: None,
+            milestones: None,
+            arbiter: None,
+            cancellation_policy: None,
+            expires_at: None,
+            valid_for_days: None,
+            payout_address: None,
+            funding_tokens: None,
+            organization: None,
+            line_items: None,
+            memo: None,
+        });
 
         let _res = execute(deps.as_mut(), mock_env(), info, exec_msg).unwrap();
 
@@ -284,17 +468,29 @@ mod tests {
             }],
         );
 
-        let exec_msg = ExecuteMsg::SubmitInvoice {
+        let exec_msg = ExecuteMsg::SubmitInvoice(SubmitInvoiceMsg {
             purpose: "building".to_string(),
             //amount: Uint128::new(3),
             amount: 3,
             admin_charge: Uint128::new(3),
             customer_charge: Uint128::new(3),
-            payer: "secret1py4ryg3atyz5cru2m64p0mtga5y09q5a26pa7n".to_string(),
+            payer: Some("secret1py4ryg3atyz5cru2m64p0mtga5y09q5a26pa7n".to_string()),
             days: 2,
             recurrent_time: Some(2),
             token: Token::Native("uscrt".to_string()),
-        };
+            late_payment: None,
+            early_payment: None,
+            milestones: None,
+            arbiter: None,
+            cancellation_policy: None,
+            expires_at: None,
+            valid_for_days: None,
+            payout_address: None,
+            funding_tokens: None,
+            organization: None,
+            line_items: None,
+            memo: None,
+        });
 
         let _res = execute(deps.as_mut(), mock_env(), info, exec_msg).unwrap();
 
@@ -382,4 +578,2258 @@ mod tests {
         assert_eq!("stop".to_string(), value.contract_process);
         assert_eq!(Uint128::new(0), value.invoice.amount);
     }
+
+    const PAYER: &str = "secret1py4ryg3atyz5cru2m64p0mtga5y09q5a26pa7n";
+
+    fn init_contract(deps: DepsMut) {
+        let info = mock_info("creator", &[]);
+        instantiate(deps, mock_env(), info, InstantiateMsg {}).unwrap();
+    }
+
+    fn submit(deps: DepsMut, payee: &str, amount: u128, recurrent_time: Option<u64>) {
+        let exec_msg = ExecuteMsg::SubmitInvoice(SubmitInvoiceMsg {
+            purpose: "building".to_string(),
+            amount,
+            admin_charge: Uint128::new(1),
+            customer_charge: Uint128::new(1),
+            payer: Some(PAYER.to_string()),
+            days: 2,
+            recurrent_time,
+            token: Token::Native("uscrt".to_string()),
+            late_payment: None,
+            early_payment: None,
+            milestones: None,
+            arbiter: None,
+            cancellation_policy: None,
+            expires_at: None,
+            valid_for_days: None,
+            payout_address: None,
+            funding_tokens: None,
+            organization: None,
+            line_items: None,
+            memo: None,
+        });
+        execute(deps, mock_env(), mock_info(payee, &[]), exec_msg).unwrap();
+    }
+
+    fn attribute<'a>(res: &'a Response, key: &str) -> Option<&'a str> {
+        res.attributes
+            .iter()
+            .find(|attribute| attribute.key == key)
+            .map(|attribute| attribute.value.as_str())
+    }
+
+    /// Sets the viewing key of `address` and returns it as query auth.
+    fn key_auth(deps: DepsMut, address: &str) -> QueryAuth {
+        let key = format!("{}_key", address);
+        let exec_msg = ExecuteMsg::SetViewingKey { key: key.clone() };
+        execute(deps, mock_env(), mock_info(address, &[]), exec_msg).unwrap();
+
+        QueryAuth::ViewingKey { key }
+    }
+
+    fn accept(deps: DepsMut, id: u64, funds: u128) -> StdResult<Response> {
+        let info = mock_info(
+            PAYER,
+            &[Coin {
+                denom: "uscrt".to_string(),
+                amount: Uint128::new(funds),
+            }],
+        );
+        execute(deps, mock_env(), info, ExecuteMsg::AcceptInvoice { id })
+    }
+
+    #[test]
+    fn filtered_invoice_listing() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        submit(deps.as_mut(), "anyone", 5, None);
+        submit(deps.as_mut(), "anyone", 3, Some(2));
+        submit(deps.as_mut(), "other", 9, None);
+        accept(deps.as_mut(), 2, 8).unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::FilteredInvoice {
+                owner: Addr::unchecked("anyone"),
+                filter: Some(ListingFilter {
+                    status: Some(InvoiceStatus::Pending),
+                    ..ListingFilter::default()
+                }),
+                sort_by: None,
+                descending: None,
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+        let value: InvoiceListResponse = from_binary(&res).unwrap();
+        assert_eq!(1, value.invoices.len());
+        assert_eq!(1, value.invoices[0].invoice_id);
+
+        // contracts of the payer sorted by amount, one per page
+        let list_contracts = |start_after: Option<ListingCursor>| {
+            let res = query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::FilteredContract {
+                    payer: Addr::unchecked(PAYER),
+                    filter: None,
+                    sort_by: Some(SortBy::Amount),
+                    descending: Some(true),
+                    start_after,
+                    limit: Some(1),
+                },
+            )
+            .unwrap();
+            from_binary::<ContractListResponse>(&res).unwrap()
+        };
+
+        let first = list_contracts(None);
+        assert_eq!(3, first.contracts[0].invoice_id);
+        let second = list_contracts(first.next);
+        assert_eq!(1, second.contracts[0].invoice_id);
+        let third = list_contracts(second.next);
+        assert_eq!(2, third.contracts[0].invoice_id);
+        assert_eq!(
+            InvoiceStatus::Active,
+            InvoiceStatus::of(&third.contracts[0].invoice)
+        );
+        assert_eq!(None, third.next);
+    }
+
+    #[test]
+    fn listing_index_paging() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        for amount in 1..=40 {
+            let payee = if amount % 2 == 0 { "anyone" } else { "other" };
+            submit(deps.as_mut(), payee, amount, None);
+        }
+
+        // contracts billed by one payee, by amount, across several chunks
+        let mut amounts = vec![];
+        let mut start_after = None;
+        loop {
+            let res = query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::FilteredContract {
+                    payer: Addr::unchecked(PAYER),
+                    filter: Some(ListingFilter {
+                        counterparty: Some("anyone".to_string()),
+                        ..ListingFilter::default()
+                    }),
+                    sort_by: Some(SortBy::Amount),
+                    descending: Some(true),
+                    start_after,
+                    limit: Some(7),
+                },
+            )
+            .unwrap();
+            let value: ContractListResponse = from_binary(&res).unwrap();
+            amounts.extend(
+                value
+                    .contracts
+                    .iter()
+                    .map(|contract| contract.invoice.amount.u128()),
+            );
+            start_after = value.next;
+            if start_after.is_none() {
+                break;
+            }
+        }
+
+        let expected: Vec<u128> = (1..=20).rev().map(|amount| amount * 2).collect();
+        assert_eq!(expected, amounts);
+    }
+
+    #[test]
+    fn payer_obligations() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        submit(deps.as_mut(), "anyone", 3, Some(3));
+        accept(deps.as_mut(), 1, 12).unwrap();
+
+        let first_due = mock_env().block.time.seconds() + 2 * 86400;
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::PayerObligations {
+                payer: Addr::unchecked(PAYER),
+                from: first_due,
+                to: first_due + 2 * 86400,
+            },
+        )
+        .unwrap();
+        let value: PayerObligationsResponse = from_binary(&res).unwrap();
+        assert_eq!(2, value.installments.len());
+        assert_eq!(first_due + 2 * 86400, value.installments[1].due_time);
+        assert!(value
+            .installments
+            .iter()
+            .all(|installment| installment.funded));
+        assert_eq!(Uint128::new(6), value.totals[0].amount);
+        assert_eq!(Uint128::new(6), value.totals[0].funded);
+
+        // milestones show up one by one until approved
+        let exec_msg = ExecuteMsg::SubmitInvoice(SubmitInvoiceMsg {
+            purpose: "building".to_string(),
+            amount: 10,
+            admin_charge: Uint128::new(1),
+            customer_charge: Uint128::new(1),
+            payer: Some(PAYER.to_string()),
+            days: 2,
+            recurrent_time: None,
+            token: Token::Native("uscrt".to_string()),
+            late_payment: None,
+            early_payment: None,
+            milestones: Some(MilestonePlan {
+                milestones: vec![
+                    MilestoneTerms {
+                        description: "design".to_string(),
+                        amount: Uint128::new(4),
+                        due_date: Some(first_due + 86400),
+                    },
+                    MilestoneTerms {
+                        description: "build".to_string(),
+                        amount: Uint128::new(6),
+                        due_date: None,
+                    },
+                ],
+                review_period_days: 3,
+            }),
+            arbiter: None,
+            cancellation_policy: None,
+            expires_at: None,
+            valid_for_days: None,
+            payout_address: None,
+            funding_tokens: None,
+            organization: None,
+            line_items: None,
+            memo: None,
+        });
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            exec_msg,
+        )
+        .unwrap();
+        accept(deps.as_mut(), 2, 11).unwrap();
+
+        let exec_msg = ExecuteMsg::SubmitMilestone {
+            id: 2,
+            index: 0,
+            proof: "ipfs://proof".to_string(),
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            exec_msg,
+        )
+        .unwrap();
+        let exec_msg = ExecuteMsg::ApproveMilestone { id: 2, index: 0 };
+        execute(deps.as_mut(), mock_env(), mock_info(PAYER, &[]), exec_msg).unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::PayerObligations {
+                payer: Addr::unchecked(PAYER),
+                from: first_due,
+                to: first_due + 2 * 86400,
+            },
+        )
+        .unwrap();
+        let value: PayerObligationsResponse = from_binary(&res).unwrap();
+        assert_eq!(3, value.installments.len());
+        let milestone = &value.installments[1];
+        assert_eq!(
+            (2, 1, first_due),
+            (milestone.invoice_id, milestone.index, milestone.due_time)
+        );
+        assert_eq!(Uint128::new(6), milestone.amount);
+        assert!(milestone.funded);
+        assert_eq!(Uint128::new(12), value.totals[0].amount);
+    }
+
+    #[test]
+    fn payee_statement() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        submit(deps.as_mut(), "anyone", 3, Some(2));
+        accept(deps.as_mut(), 1, 8).unwrap();
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(2 * 86400);
+        let exec_msg = ExecuteMsg::WithdrawPayment { id: 1 };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            exec_msg,
+        )
+        .unwrap();
+
+        let statement = |deps: Deps, auth: QueryAuth| {
+            let msg = QueryMsg::PayeeStatement {
+                payee: Addr::unchecked("anyone"),
+                from: 0,
+                to: env.block.time.seconds(),
+                token: Token::Native("uscrt".to_string()),
+                page: 0,
+                auth,
+            };
+            query(deps, env.clone(), msg)
+        };
+        let wrong_key = QueryAuth::ViewingKey {
+            key: "wrong_key".to_string(),
+        };
+        assert!(statement(deps.as_ref(), wrong_key).is_err());
+
+        let auth = key_auth(deps.as_mut(), "anyone");
+        let res = statement(deps.as_ref(), auth).unwrap();
+        let value: PayeeStatementResponse = from_binary(&res).unwrap();
+        assert_eq!(1, value.withdrawals.len());
+        assert_eq!(1, value.total);
+        assert_eq!(Uint128::new(3), value.total_gross_amount);
+        assert_eq!(Uint128::new(1), value.total_fee);
+        assert_eq!(Uint128::new(2), value.total_net_amount);
+    }
+
+    #[test]
+    fn invoice_history() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        submit(deps.as_mut(), "anyone", 3, Some(2));
+        accept(deps.as_mut(), 1, 8).unwrap();
+
+        for address in ["anyone", PAYER, "stranger"] {
+            key_auth(deps.as_mut(), address);
+        }
+
+        let history = |address: &str, key: &str| {
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::InvoiceHistory {
+                    id: 1,
+                    address: Addr::unchecked(address),
+                    page: 0,
+                    page_size: 10,
+                    auth: QueryAuth::ViewingKey {
+                        key: format!("{}_key", key),
+                    },
+                },
+            )
+        };
+
+        let value: InvoiceHistoryResponse =
+            from_binary(&history("anyone", "anyone").unwrap()).unwrap();
+        assert_eq!(3, value.total);
+        let kinds: Vec<_> = value.events.iter().map(|event| event.kind).collect();
+        assert_eq!(
+            vec![
+                InvoiceEventKind::Submitted,
+                InvoiceEventKind::Accepted,
+                InvoiceEventKind::Deposited
+            ],
+            kinds
+        );
+        assert_eq!(Uint128::new(6), value.events[2].amount);
+        assert_eq!(Uint128::new(2), value.events[2].fee);
+
+        assert!(history(PAYER, PAYER).is_ok());
+        assert!(history("stranger", "stranger").is_err());
+        // a party address without its key is not enough
+        assert!(history("anyone", "stranger").is_err());
+    }
+
+    #[test]
+    fn withdraw_response() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        submit(deps.as_mut(), "anyone", 3, Some(2));
+        let res = accept(deps.as_mut(), 1, 8).unwrap();
+        assert_eq!(1, res.messages.len());
+        assert_eq!(Some("accept_invoice"), attribute(&res, "action"));
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(2 * 86400);
+        let exec_msg = ExecuteMsg::WithdrawPayment { id: 1 };
+        let res = execute(deps.as_mut(), env, mock_info("anyone", &[]), exec_msg).unwrap();
+
+        // payee payment and admin charge
+        assert_eq!(2, res.messages.len());
+        assert_eq!(Some("withdraw_payment"), attribute(&res, "action"));
+        assert_eq!(Some("1"), attribute(&res, "invoice_id"));
+        assert_eq!(Some("3"), attribute(&res, "amount"));
+        assert_eq!(Some("1"), attribute(&res, "fee"));
+        assert_eq!(Some("uscrt"), attribute(&res, "token"));
+    }
+
+    #[test]
+    fn partial_funding() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        // one payment costs 3 and 1 of admin charge
+        submit(deps.as_mut(), "anyone", 3, Some(3));
+        assert!(accept(deps.as_mut(), 1, 3).is_err());
+        accept(deps.as_mut(), 1, 4).unwrap();
+
+        let funding_status = |deps: Deps| {
+            let msg = QueryMsg::FundingStatus {
+                id: 1,
+                payer: Addr::unchecked(PAYER),
+            };
+            from_binary::<FundingStatusResponse>(&query(deps, mock_env(), msg).unwrap()).unwrap()
+        };
+        let value = funding_status(deps.as_ref());
+        assert_eq!(1, value.covered);
+        assert!(!value.fully_funded);
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(4 * 86400);
+        let withdraw = |deps: DepsMut| {
+            let exec_msg = ExecuteMsg::WithdrawPayment { id: 1 };
+            execute(deps, env.clone(), mock_info("anyone", &[]), exec_msg)
+        };
+        withdraw(deps.as_mut()).unwrap();
+
+        // escrow is dry until the payer tops up
+        assert!(withdraw(deps.as_mut()).is_err());
+
+        let info = mock_info(
+            PAYER,
+            &[Coin {
+                denom: "uscrt".to_string(),
+                amount: Uint128::new(8),
+            }],
+        );
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::TopUp { id: 1 }).unwrap();
+
+        let value = funding_status(deps.as_ref());
+        assert_eq!(2, value.covered);
+        assert_eq!(Uint128::new(6), value.account_balance);
+        assert!(value.fully_funded);
+
+        withdraw(deps.as_mut()).unwrap();
+    }
+
+    #[test]
+    fn treasury_funding() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        submit(deps.as_mut(), "anyone", 3, Some(2));
+        accept(deps.as_mut(), 1, 4).unwrap();
+
+        let info = mock_info(
+            PAYER,
+            &[Coin {
+                denom: "uscrt".to_string(),
+                amount: Uint128::new(5),
+            }],
+        );
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::DepositTreasury {},
+        )
+        .unwrap();
+
+        let treasury = |deps: Deps| {
+            let msg = QueryMsg::Treasury {
+                payer: Addr::unchecked(PAYER),
+            };
+            from_binary::<TreasuryResponse>(&query(deps, mock_env(), msg).unwrap()).unwrap()
+        };
+        assert_eq!(Uint128::new(0), treasury(deps.as_ref()).next_payments);
+
+        let withdraw = |deps: DepsMut, days: u64| {
+            let mut env = mock_env();
+            env.block.time = env.block.time.plus_seconds(days * 86400);
+            let exec_msg = ExecuteMsg::WithdrawPayment { id: 1 };
+            execute(deps, env, mock_info("anyone", &[]), exec_msg)
+        };
+        withdraw(deps.as_mut(), 2).unwrap();
+
+        // the second payment is not due before the next period
+        assert!(withdraw(deps.as_mut(), 2).is_err());
+        assert_eq!(Uint128::new(5), treasury(deps.as_ref()).balance);
+
+        // then it is drawn from the treasury
+        let value = treasury(deps.as_ref());
+        assert_eq!(Uint128::new(4), value.next_payments);
+        assert!(!value.low_balance);
+        withdraw(deps.as_mut(), 4).unwrap();
+        assert_eq!(Uint128::new(1), treasury(deps.as_ref()).balance);
+
+        let exec_msg = ExecuteMsg::WithdrawTreasury {
+            amount: Uint128::new(2),
+        };
+        assert!(execute(deps.as_mut(), mock_env(), mock_info(PAYER, &[]), exec_msg).is_err());
+        let exec_msg = ExecuteMsg::WithdrawTreasury {
+            amount: Uint128::new(1),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info(PAYER, &[]), exec_msg).unwrap();
+        assert_eq!(1, res.messages.len());
+    }
+
+    #[test]
+    fn late_payment_penalty() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        let exec_msg = ExecuteMsg::SubmitInvoice(SubmitInvoiceMsg {
+            purpose: "building".to_string(),
+            amount: 3,
+            admin_charge: Uint128::new(1),
+            customer_charge: Uint128::new(1),
+            payer: Some(PAYER.to_string()),
+            days: 2,
+            recurrent_time: Some(2),
+            token: Token::Native("uscrt".to_string()),
+            late_payment: Some(LatePaymentTerms {
+                grace_period_days: 1,
+                late_fee: LateFee::Flat {
+                    amount: Uint128::new(2),
+                },
+            }),
+            early_payment: None,
+            milestones: None,
+            arbiter: None,
+            cancellation_policy: None,
+            expires_at: None,
+            valid_for_days: None,
+            payout_address: None,
+            funding_tokens: None,
+            organization: None,
+            line_items: None,
+            memo: None,
+        });
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            exec_msg,
+        )
+        .unwrap();
+        accept(deps.as_mut(), 1, 4).unwrap();
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(2 * 86400);
+        let exec_msg = ExecuteMsg::WithdrawPayment { id: 1 };
+        execute(deps.as_mut(), env, mock_info("anyone", &[]), exec_msg).unwrap();
+
+        // the second payment is due after 4 days and late after 5
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(8 * 86400);
+        let funding_status = |deps: Deps| {
+            let msg = QueryMsg::FundingStatus {
+                id: 1,
+                payer: Addr::unchecked(PAYER),
+            };
+            from_binary::<FundingStatusResponse>(&query(deps, env.clone(), msg).unwrap()).unwrap()
+        };
+        let value = funding_status(deps.as_ref());
+        assert_eq!(
+            Some(mock_env().block.time.seconds() + 5 * 86400),
+            value.overdue_since
+        );
+        assert_eq!(Uint128::new(6), value.accrued_penalty);
+
+        // the penalty is paid to the payee before the payment is funded
+        let info = mock_info(
+            PAYER,
+            &[Coin {
+                denom: "uscrt".to_string(),
+                amount: Uint128::new(9),
+            }],
+        );
+        assert!(execute(
+            deps.as_mut(),
+            env.clone(),
+            info,
+            ExecuteMsg::TopUp { id: 1 }
+        )
+        .is_err());
+        let info = mock_info(
+            PAYER,
+            &[Coin {
+                denom: "uscrt".to_string(),
+                amount: Uint128::new(10),
+            }],
+        );
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            info,
+            ExecuteMsg::TopUp { id: 1 },
+        )
+        .unwrap();
+        assert_eq!(Some("6"), attribute(&res, "penalty"));
+        assert_eq!(2, res.messages.len());
+
+        let value = funding_status(deps.as_ref());
+        assert_eq!(None, value.overdue_since);
+        assert_eq!(Uint128::zero(), value.accrued_penalty);
+        assert!(value.fully_funded);
+    }
+
+    #[test]
+    fn late_payment_treasury() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        let exec_msg = ExecuteMsg::SubmitInvoice(SubmitInvoiceMsg {
+            purpose: "building".to_string(),
+            amount: 3,
+            admin_charge: Uint128::new(1),
+            customer_charge: Uint128::new(1),
+            payer: Some(PAYER.to_string()),
+            days: 2,
+            recurrent_time: Some(2),
+            token: Token::Native("uscrt".to_string()),
+            late_payment: Some(LatePaymentTerms {
+                grace_period_days: 1,
+                late_fee: LateFee::Flat {
+                    amount: Uint128::new(2),
+                },
+            }),
+            early_payment: None,
+            milestones: None,
+            arbiter: None,
+            cancellation_policy: None,
+            expires_at: None,
+            valid_for_days: None,
+            payout_address: None,
+            funding_tokens: None,
+            organization: None,
+            line_items: None,
+            memo: None,
+        });
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            exec_msg,
+        )
+        .unwrap();
+        accept(deps.as_mut(), 1, 4).unwrap();
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(2 * 86400);
+        let exec_msg = ExecuteMsg::WithdrawPayment { id: 1 };
+        execute(deps.as_mut(), env, mock_info("anyone", &[]), exec_msg).unwrap();
+
+        let treasury_msg = |amount: u128| {
+            mock_info(
+                PAYER,
+                &[Coin {
+                    denom: "uscrt".to_string(),
+                    amount: Uint128::new(amount),
+                }],
+            )
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            treasury_msg(4),
+            ExecuteMsg::DepositTreasury {},
+        )
+        .unwrap();
+
+        let at_day = |days: u64| {
+            let mut env = mock_env();
+            env.block.time = env.block.time.plus_seconds(days * 86400);
+            env
+        };
+        let funding_status = |deps: Deps, env: Env| {
+            let msg = QueryMsg::FundingStatus {
+                id: 1,
+                payer: Addr::unchecked(PAYER),
+            };
+            from_binary::<FundingStatusResponse>(&query(deps, env, msg).unwrap()).unwrap()
+        };
+
+        // the treasury covers the second payment, so it is not late
+        let value = funding_status(deps.as_ref(), at_day(8));
+        assert_eq!(None, value.overdue_since);
+        assert_eq!(Uint128::zero(), value.accrued_penalty);
+
+        let exec_msg = ExecuteMsg::WithdrawTreasury {
+            amount: Uint128::new(4),
+        };
+        let res = execute(deps.as_mut(), at_day(8), mock_info(PAYER, &[]), exec_msg).unwrap();
+        assert_eq!(Some("0"), attribute(&res, "penalty"));
+
+        // the penalty only runs from the withdrawal on
+        let value = funding_status(deps.as_ref(), at_day(10));
+        assert_eq!(
+            Some(mock_env().block.time.seconds() + 5 * 86400),
+            value.overdue_since
+        );
+        assert_eq!(Uint128::new(4), value.accrued_penalty);
+
+        // and is paid to the payee out of the next treasury deposit
+        let res = execute(
+            deps.as_mut(),
+            at_day(10),
+            treasury_msg(10),
+            ExecuteMsg::DepositTreasury {},
+        )
+        .unwrap();
+        assert_eq!(Some("4"), attribute(&res, "penalty"));
+        assert_eq!(Some("6"), attribute(&res, "balance"));
+        assert_eq!(1, res.messages.len());
+
+        let value = funding_status(deps.as_ref(), at_day(10));
+        assert_eq!(None, value.overdue_since);
+        assert_eq!(Uint128::zero(), value.accrued_penalty);
+    }
+
+    #[test]
+    fn early_payment_discount() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        // 2% off when funded within 10 days
+        let submit_discounted = |deps: DepsMut, customer_charge: u128| {
+            let exec_msg = ExecuteMsg::SubmitInvoice(SubmitInvoiceMsg {
+                purpose: "building".to_string(),
+                amount: 100,
+                admin_charge: Uint128::new(1),
+                customer_charge: Uint128::new(customer_charge),
+                payer: Some(PAYER.to_string()),
+                days: 2,
+                recurrent_time: None,
+                token: Token::Native("uscrt".to_string()),
+                late_payment: None,
+                early_payment: Some(EarlyPaymentTerms {
+                    basis_points: 200,
+                    deadline_days: 10,
+                }),
+                milestones: None,
+                arbiter: None,
+                cancellation_policy: None,
+                expires_at: None,
+                valid_for_days: None,
+                payout_address: None,
+                funding_tokens: None,
+                organization: None,
+                line_items: None,
+                memo: None,
+            });
+            execute(deps, mock_env(), mock_info("anyone", &[]), exec_msg)
+        };
+        for _ in 0..2 {
+            submit_discounted(deps.as_mut(), 1).unwrap();
+        }
+
+        let accept_at = |deps: DepsMut, id: u64, days: u64, funds: u128| {
+            let mut env = mock_env();
+            env.block.time = env.block.time.plus_seconds(days * 86400);
+            let info = mock_info(
+                PAYER,
+                &[Coin {
+                    denom: "uscrt".to_string(),
+                    amount: Uint128::new(funds),
+                }],
+            );
+            execute(deps, env, info, ExecuteMsg::AcceptInvoice { id })
+        };
+        assert!(accept_at(deps.as_mut(), 1, 5, 98).is_err());
+        accept_at(deps.as_mut(), 1, 5, 99).unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::SingleContract {
+                id: 1,
+                payer: Addr::unchecked(PAYER),
+            },
+        )
+        .unwrap();
+        let value: Contract = from_binary(&res).unwrap();
+        assert_eq!(Uint128::new(98), value.invoice.amount);
+        assert_eq!(Uint128::new(2), value.invoice.discount);
+        assert_eq!(98, value.account_balance);
+
+        // the discount is lost after the deadline
+        assert!(accept_at(deps.as_mut(), 2, 11, 99).is_err());
+        accept_at(deps.as_mut(), 2, 11, 101).unwrap();
+
+        // the discounted amount of 98 would not cover the charge on payee
+        assert!(submit_discounted(deps.as_mut(), 99).is_err());
+    }
+
+    #[test]
+    fn milestone_approval() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        let exec_msg = ExecuteMsg::SubmitInvoice(SubmitInvoiceMsg {
+            purpose: "building".to_string(),
+            amount: 10,
+            admin_charge: Uint128::new(1),
+            customer_charge: Uint128::new(1),
+            payer: Some(PAYER.to_string()),
+            days: 2,
+            recurrent_time: None,
+            token: Token::Native("uscrt".to_string()),
+            late_payment: None,
+            early_payment: None,
+            milestones: Some(MilestonePlan {
+                milestones: vec![
+                    MilestoneTerms {
+                        description: "design".to_string(),
+                        amount: Uint128::new(4),
+                        due_date: None,
+                    },
+                    MilestoneTerms {
+                        description: "build".to_string(),
+                        amount: Uint128::new(6),
+                        due_date: None,
+                    },
+                ],
+                review_period_days: 3,
+            }),
+            arbiter: None,
+            cancellation_policy: None,
+            expires_at: None,
+            valid_for_days: None,
+            payout_address: None,
+            funding_tokens: None,
+            organization: None,
+            line_items: None,
+            memo: None,
+        });
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            exec_msg,
+        )
+        .unwrap();
+        accept(deps.as_mut(), 1, 11).unwrap();
+
+        let submit_milestone = |deps: DepsMut, index: u32| {
+            let exec_msg = ExecuteMsg::SubmitMilestone {
+                id: 1,
+                index,
+                proof: "ipfs://proof".to_string(),
+            };
+            execute(deps, mock_env(), mock_info("anyone", &[]), exec_msg)
+        };
+        let approve_milestone = |deps: DepsMut, sender: &str, index: u32, days: u64| {
+            let mut env = mock_env();
+            env.block.time = env.block.time.plus_seconds(days * 86400);
+            let exec_msg = ExecuteMsg::ApproveMilestone { id: 1, index };
+            execute(deps, env, mock_info(sender, &[]), exec_msg)
+        };
+
+        assert!(approve_milestone(deps.as_mut(), PAYER, 0, 0).is_err());
+        submit_milestone(deps.as_mut(), 0).unwrap();
+        assert!(submit_milestone(deps.as_mut(), 0).is_err());
+
+        // the payee has to wait for the review period
+        assert!(approve_milestone(deps.as_mut(), "anyone", 0, 1).is_err());
+        let res = approve_milestone(deps.as_mut(), PAYER, 0, 1).unwrap();
+        assert_eq!(1, res.messages.len());
+        assert_eq!(Some("0"), attribute(&res, "fee"));
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(5 * 86400);
+        let exec_msg = ExecuteMsg::WithdrawPayment { id: 1 };
+        assert!(execute(deps.as_mut(), env, mock_info("anyone", &[]), exec_msg).is_err());
+
+        // the payee approves it once the payer lets the review period pass
+        submit_milestone(deps.as_mut(), 1).unwrap();
+        let res = approve_milestone(deps.as_mut(), "anyone", 1, 3).unwrap();
+        assert_eq!(2, res.messages.len());
+        assert_eq!(Some("1"), attribute(&res, "fee"));
+
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::SingleContract {
+                id: 1,
+                payer: Addr::unchecked(PAYER),
+            },
+        )
+        .unwrap();
+        let value: Contract = from_binary(&res).unwrap();
+        assert_eq!("done", value.contract_process);
+        assert_eq!(0, value.account_balance);
+    }
+
+    #[test]
+    fn dispute_resolution() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        submit(deps.as_mut(), "anyone", 3, Some(2));
+        accept(deps.as_mut(), 1, 8).unwrap();
+
+        let raise_dispute = |deps: DepsMut| {
+            let exec_msg = ExecuteMsg::RaiseDispute {
+                id: 1,
+                reason: "work not delivered".to_string(),
+            };
+            execute(deps, mock_env(), mock_info(PAYER, &[]), exec_msg)
+        };
+
+        // no arbiter has been chosen yet
+        assert!(raise_dispute(deps.as_mut()).is_err());
+        let exec_msg = ExecuteMsg::SetArbiter {
+            arbiter: "arbiter".to_string(),
+        };
+        assert!(execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(PAYER, &[]),
+            exec_msg.clone()
+        )
+        .is_err());
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            exec_msg,
+        )
+        .unwrap();
+        raise_dispute(deps.as_mut()).unwrap();
+
+        // escrow is frozen
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(2 * 86400);
+        let exec_msg = ExecuteMsg::WithdrawPayment { id: 1 };
+        assert!(execute(deps.as_mut(), env, mock_info("anyone", &[]), exec_msg).is_err());
+        let exec_msg = ExecuteMsg::CancelPayment { id: 1 };
+        assert!(execute(deps.as_mut(), mock_env(), mock_info(PAYER, &[]), exec_msg).is_err());
+
+        for address in ["stranger", "arbiter", "anyone"] {
+            key_auth(deps.as_mut(), address);
+        }
+        let dispute = |deps: Deps, address: &str| {
+            let msg = QueryMsg::Dispute {
+                id: 1,
+                address: Addr::unchecked(address),
+                auth: QueryAuth::ViewingKey {
+                    key: format!("{}_key", address),
+                },
+            };
+            query(deps, mock_env(), msg).and_then(|res| from_binary::<DisputeResponse>(&res))
+        };
+        assert!(dispute(deps.as_ref(), "stranger").is_err());
+        let value = dispute(deps.as_ref(), "arbiter").unwrap();
+        assert_eq!(PAYER, value.open.unwrap().raised_by);
+
+        let resolve_dispute = |deps: DepsMut, sender: &str, payee_share: u128| {
+            let exec_msg = ExecuteMsg::ResolveDispute {
+                id: 1,
+                payee_share: Uint128::new(payee_share),
+                payer_share: Uint128::new(2),
+            };
+            execute(deps, mock_env(), mock_info(sender, &[]), exec_msg)
+        };
+        assert!(resolve_dispute(deps.as_mut(), PAYER, 4).is_err());
+        // shares must add up to the balance of 6
+        assert!(resolve_dispute(deps.as_mut(), "arbiter", 3).is_err());
+        let res = resolve_dispute(deps.as_mut(), "arbiter", 4).unwrap();
+        assert_eq!(2, res.messages.len());
+
+        let value = dispute(deps.as_ref(), "anyone").unwrap();
+        assert_eq!(None, value.open);
+        assert_eq!(1, value.history.len());
+        assert_eq!(
+            Uint128::new(4),
+            value.history[0].resolution.as_ref().unwrap().payee_share
+        );
+    }
+
+    #[test]
+    fn cancellation_policy() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        // payments of 3 due after 2, 4, 6 and 8 days
+        for protect_earned in [true, false] {
+            let exec_msg = ExecuteMsg::SubmitInvoice(SubmitInvoiceMsg {
+                purpose: "building".to_string(),
+                amount: 3,
+                admin_charge: Uint128::new(1),
+                customer_charge: Uint128::new(1),
+                payer: Some(PAYER.to_string()),
+                days: 2,
+                recurrent_time: Some(4),
+                token: Token::Native("uscrt".to_string()),
+                late_payment: None,
+                early_payment: None,
+                milestones: None,
+                arbiter: None,
+                cancellation_policy: Some(CancellationPolicy {
+                    notice_days: 2,
+                    kill_fee_basis_points: 5000,
+                    protect_earned,
+                }),
+                expires_at: None,
+                valid_for_days: None,
+                payout_address: None,
+                funding_tokens: None,
+                organization: None,
+                line_items: None,
+                memo: None,
+            });
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("anyone", &[]),
+                exec_msg,
+            )
+            .unwrap();
+        }
+        accept(deps.as_mut(), 1, 16).unwrap();
+        accept(deps.as_mut(), 2, 16).unwrap();
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(3 * 86400);
+        let cancel = |deps: DepsMut, id: u64| {
+            let exec_msg = ExecuteMsg::CancelPayment { id };
+            execute(deps, env.clone(), mock_info(PAYER, &[]), exec_msg).unwrap()
+        };
+
+        // earned and notice payments plus half of the two payments left
+        let res = cancel(deps.as_mut(), 1);
+        assert_eq!(Some("9"), attribute(&res, "payee_share"));
+        assert_eq!(Some("3"), attribute(&res, "amount"));
+
+        // the payment already due is not protected
+        let res = cancel(deps.as_mut(), 2);
+        assert_eq!(Some("6"), attribute(&res, "payee_share"));
+        assert_eq!(Some("6"), attribute(&res, "amount"));
+
+        let exec_msg = ExecuteMsg::WithdrawPayment { id: 1 };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            exec_msg,
+        )
+        .unwrap();
+        assert_eq!(Some("9"), attribute(&res, "amount"));
+    }
+
+    #[test]
+    fn terminate_with_notice() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        // payments of 3 due after 2, 4, 6 and 8 days
+        submit(deps.as_mut(), "anyone", 3, Some(4));
+        accept(deps.as_mut(), 1, 16).unwrap();
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(3 * 86400);
+        let terminate = |deps: DepsMut| {
+            let exec_msg = ExecuteMsg::TerminateWithNotice { id: 1 };
+            execute(deps, env.clone(), mock_info(PAYER, &[]), exec_msg)
+        };
+
+        // the payment due and one notice period are kept
+        let res = terminate(deps.as_mut()).unwrap();
+        assert_eq!(Some("6"), attribute(&res, "amount"));
+        assert_eq!(Some("2"), attribute(&res, "remaining_payments"));
+        let final_payout_time = mock_env().block.time.seconds() + 4 * 86400;
+        assert_eq!(
+            Some(final_payout_time.to_string().as_str()),
+            attribute(&res, "final_payout_time")
+        );
+        assert!(terminate(deps.as_mut()).is_err());
+
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::SingleContract {
+                id: 1,
+                payer: Addr::unchecked(PAYER),
+            },
+        )
+        .unwrap();
+        let value: Contract = from_binary(&res).unwrap();
+        assert_eq!(6, value.account_balance);
+        assert_eq!(2, value.invoice.remaining_time_of_payment);
+        assert_eq!(Some(final_payout_time), value.invoice.final_payout_time);
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(4 * 86400);
+        let withdraw = |deps: DepsMut| {
+            let exec_msg = ExecuteMsg::WithdrawPayment { id: 1 };
+            execute(deps, env.clone(), mock_info("anyone", &[]), exec_msg)
+        };
+        withdraw(deps.as_mut()).unwrap();
+        withdraw(deps.as_mut()).unwrap();
+        assert!(withdraw(deps.as_mut()).is_err());
+    }
+
+    #[test]
+    fn resign() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        // payments of 3 due after 2, 4, 6 and 8 days
+        submit(deps.as_mut(), "anyone", 3, Some(4));
+        accept(deps.as_mut(), 1, 16).unwrap();
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(5 * 86400);
+        let resign = |deps: DepsMut, sender: &str| {
+            let exec_msg = ExecuteMsg::Resign { id: 1 };
+            execute(deps, env.clone(), mock_info(sender, &[]), exec_msg)
+        };
+        assert!(resign(deps.as_mut(), PAYER).is_err());
+
+        // the two payments due are paid out and the other two refunded
+        let res = resign(deps.as_mut(), "anyone").unwrap();
+        assert_eq!(3, res.messages.len());
+        assert_eq!(Some("6"), attribute(&res, "payee_share"));
+        assert_eq!(Some("2"), attribute(&res, "fee"));
+        assert_eq!(Some("6"), attribute(&res, "amount"));
+        assert!(resign(deps.as_mut(), "anyone").is_err());
+
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::SingleContract {
+                id: 1,
+                payer: Addr::unchecked(PAYER),
+            },
+        )
+        .unwrap();
+        let value: Contract = from_binary(&res).unwrap();
+        assert_eq!("stop", value.contract_process);
+        assert_eq!(0, value.account_balance);
+    }
+
+    #[test]
+    fn contract_amendment() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        submit(deps.as_mut(), "anyone", 3, Some(4));
+        accept(deps.as_mut(), 1, 16).unwrap();
+
+        let propose = |deps: DepsMut, sender: &str, new_amount: u128, new_count: u64| {
+            let exec_msg = ExecuteMsg::ProposeAmendment {
+                id: 1,
+                new_amount: Uint128::new(new_amount),
+                new_period: 2,
+                new_count,
+            };
+            execute(deps, mock_env(), mock_info(sender, &[]), exec_msg)
+        };
+        let accept_amendment = |deps: DepsMut, sender: &str, funds: u128| {
+            let info = mock_info(
+                sender,
+                &[Coin {
+                    denom: "uscrt".to_string(),
+                    amount: Uint128::new(funds),
+                }],
+            );
+            execute(
+                deps,
+                mock_env(),
+                info,
+                ExecuteMsg::AcceptAmendment { id: 1 },
+            )
+        };
+
+        // a raise needs 4 more in escrow for the four payments covered
+        propose(deps.as_mut(), "anyone", 4, 4).unwrap();
+        assert!(accept_amendment(deps.as_mut(), "anyone", 0).is_err());
+        assert!(accept_amendment(deps.as_mut(), PAYER, 0).is_err());
+        accept_amendment(deps.as_mut(), PAYER, 4).unwrap();
+
+        // a shorter contract refunds the escrow of the payments dropped
+        propose(deps.as_mut(), PAYER, 2, 2).unwrap();
+        let res = accept_amendment(deps.as_mut(), "anyone", 0).unwrap();
+        assert_eq!(Some("12"), attribute(&res, "amount"));
+        assert_eq!(1, res.messages.len());
+
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::SingleContract {
+                id: 1,
+                payer: Addr::unchecked(PAYER),
+            },
+        )
+        .unwrap();
+        let value: Contract = from_binary(&res).unwrap();
+        assert_eq!(2, value.amendments.len());
+        assert_eq!(None, value.pending_amendment);
+        assert_eq!(4, value.account_balance);
+        assert_eq!(Uint128::new(2), value.invoice.amount);
+        assert_eq!(2, value.invoice.remaining_time_of_payment);
+
+        // a termination drops the pending proposal for good
+        propose(deps.as_mut(), "anyone", 3, 2).unwrap();
+        let exec_msg = ExecuteMsg::TerminateWithNotice { id: 1 };
+        execute(deps.as_mut(), mock_env(), mock_info(PAYER, &[]), exec_msg).unwrap();
+        assert!(accept_amendment(deps.as_mut(), PAYER, 2).is_err());
+
+        // payments of 3 with a charge of 2 on the payee
+        let exec_msg = ExecuteMsg::SubmitInvoice(SubmitInvoiceMsg {
+            purpose: "building".to_string(),
+            amount: 3,
+            admin_charge: Uint128::new(1),
+            customer_charge: Uint128::new(2),
+            payer: Some(PAYER.to_string()),
+            days: 2,
+            recurrent_time: Some(2),
+            token: Token::Native("uscrt".to_string()),
+            late_payment: None,
+            early_payment: None,
+            milestones: None,
+            arbiter: None,
+            cancellation_policy: None,
+            expires_at: None,
+            valid_for_days: None,
+            payout_address: None,
+            funding_tokens: None,
+            organization: None,
+            line_items: None,
+            memo: None,
+        });
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            exec_msg,
+        )
+        .unwrap();
+        accept(deps.as_mut(), 2, 8).unwrap();
+
+        let propose_to = |deps: DepsMut, new_amount: u128| {
+            let exec_msg = ExecuteMsg::ProposeAmendment {
+                id: 2,
+                new_amount: Uint128::new(new_amount),
+                new_period: 2,
+                new_count: 2,
+            };
+            execute(deps, mock_env(), mock_info(PAYER, &[]), exec_msg)
+        };
+        assert!(propose_to(deps.as_mut(), 1).is_err());
+        propose_to(deps.as_mut(), 2).unwrap();
+        let exec_msg = ExecuteMsg::AcceptAmendment { id: 2 };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            exec_msg,
+        )
+        .unwrap();
+
+        // the whole payment goes to the charge
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(2 * 86400);
+        let exec_msg = ExecuteMsg::WithdrawPayment { id: 2 };
+        let res = execute(deps.as_mut(), env, mock_info("anyone", &[]), exec_msg).unwrap();
+        assert_eq!(Some("2"), attribute(&res, "fee"));
+    }
+
+    #[test]
+    fn invoice_expiry() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        let submit_valid_for = |deps: DepsMut, expires_at: Option<u64>| {
+            let exec_msg = ExecuteMsg::SubmitInvoice(SubmitInvoiceMsg {
+                purpose: "building".to_string(),
+                amount: 3,
+                admin_charge: Uint128::new(1),
+                customer_charge: Uint128::new(1),
+                payer: Some(PAYER.to_string()),
+                days: 2,
+                recurrent_time: None,
+                token: Token::Native("uscrt".to_string()),
+                late_payment: None,
+                early_payment: None,
+                milestones: None,
+                arbiter: None,
+                cancellation_policy: None,
+                expires_at,
+                valid_for_days: Some(3),
+                payout_address: None,
+                funding_tokens: None,
+                organization: None,
+                line_items: None,
+                memo: None,
+            });
+            execute(deps, mock_env(), mock_info("anyone", &[]), exec_msg)
+        };
+        submit_valid_for(deps.as_mut(), None).unwrap();
+        submit_valid_for(deps.as_mut(), None).unwrap();
+        assert!(submit_valid_for(deps.as_mut(), Some(1)).is_err());
+
+        let accept_at = |deps: DepsMut, id: u64, days: u64| {
+            let mut env = mock_env();
+            env.block.time = env.block.time.plus_seconds(days * 86400);
+            let info = mock_info(
+                PAYER,
+                &[Coin {
+                    denom: "uscrt".to_string(),
+                    amount: Uint128::new(4),
+                }],
+            );
+            execute(deps, env, info, ExecuteMsg::AcceptInvoice { id })
+        };
+        accept_at(deps.as_mut(), 2, 1).unwrap();
+        assert!(accept_at(deps.as_mut(), 1, 3).is_err());
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(3 * 86400);
+        let res = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::FilteredInvoice {
+                owner: Addr::unchecked("anyone"),
+                filter: None,
+                sort_by: None,
+                descending: None,
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+        let value: InvoiceListResponse = from_binary(&res).unwrap();
+        assert_eq!(1, value.invoices.len());
+        assert_eq!(2, value.invoices[0].invoice_id);
+
+        // only the expired invoice is purged
+        let exec_msg = ExecuteMsg::PurgeExpired { limit: None };
+        let res = execute(deps.as_mut(), env, mock_info("anyone", &[]), exec_msg).unwrap();
+        assert_eq!(Some("1"), attribute(&res, "purged"));
+        assert_eq!(0, HistoryStore::num_event(deps.as_ref().storage, 1));
+        assert_eq!(3, HistoryStore::num_event(deps.as_ref().storage, 2));
+
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::NumberOfContract {
+                payer: Addr::unchecked(PAYER),
+            },
+        )
+        .unwrap();
+        let value: u32 = from_binary(&res).unwrap();
+        assert_eq!(1, value);
+    }
+
+    #[test]
+    fn payout_address() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        submit(deps.as_mut(), "anyone", 3, None);
+        accept(deps.as_mut(), 1, 4).unwrap();
+
+        let set_payout_address = |deps: DepsMut, sender: &str| {
+            let exec_msg = ExecuteMsg::SetPayoutAddress {
+                id: 1,
+                payout_address: Some("cold_wallet".to_string()),
+            };
+            execute(deps, mock_env(), mock_info(sender, &[]), exec_msg)
+        };
+        assert!(set_payout_address(deps.as_mut(), PAYER).is_err());
+        set_payout_address(deps.as_mut(), "anyone").unwrap();
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(2 * 86400);
+        let exec_msg = ExecuteMsg::WithdrawPayment { id: 1 };
+        let res = execute(deps.as_mut(), env, mock_info("anyone", &[]), exec_msg).unwrap();
+        assert_eq!(
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "cold_wallet".to_string(),
+                amount: coins(2, "uscrt"),
+            }),
+            res.messages[0].msg
+        );
+    }
+
+    #[test]
+    fn payout_split() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        submit(deps.as_mut(), "anyone", 10, None);
+        accept(deps.as_mut(), 1, 11).unwrap();
+
+        let set_payout_split = |deps: DepsMut, basis_points: u64| {
+            let exec_msg = ExecuteMsg::SetPayoutSplit {
+                id: 1,
+                split: vec![
+                    PayoutShare {
+                        address: "savings".to_string(),
+                        basis_points: 3333,
+                    },
+                    PayoutShare {
+                        address: "spending".to_string(),
+                        basis_points,
+                    },
+                ],
+            };
+            execute(deps, mock_env(), mock_info("anyone", &[]), exec_msg)
+        };
+        assert!(set_payout_split(deps.as_mut(), 6000).is_err());
+        set_payout_split(deps.as_mut(), 6667).unwrap();
+
+        // the net payment of 9 is split without losing the rounding remainder
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(2 * 86400);
+        let exec_msg = ExecuteMsg::WithdrawPayment { id: 1 };
+        let res = execute(deps.as_mut(), env, mock_info("anyone", &[]), exec_msg).unwrap();
+        assert_eq!(
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "savings".to_string(),
+                amount: coins(2, "uscrt"),
+            }),
+            res.messages[0].msg
+        );
+        assert_eq!(
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "spending".to_string(),
+                amount: coins(7, "uscrt"),
+            }),
+            res.messages[1].msg
+        );
+    }
+
+    #[test]
+    fn funding_token() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        let msg = SubmitInvoiceMsg {
+            purpose: "building".to_string(),
+            amount: 10,
+            admin_charge: Uint128::new(1),
+            customer_charge: Uint128::new(1),
+            payer: Some(PAYER.to_string()),
+            days: 2,
+            recurrent_time: None,
+            token: Token::Native("uscrt".to_string()),
+            late_payment: None,
+            early_payment: None,
+            milestones: None,
+            arbiter: None,
+            cancellation_policy: None,
+            expires_at: None,
+            valid_for_days: None,
+            payout_address: None,
+            funding_tokens: Some(vec![Token::Native("usdc".to_string())]),
+            organization: None,
+            line_items: None,
+            memo: None,
+        };
+
+        // SNIP-20 tokens can not be paid out with bank messages
+        let snip20 = SubmitInvoiceMsg {
+            token: Token::Snip20(secret_toolkit::utils::types::Contract {
+                address: "secret1snip20token".to_string(),
+                hash: "hash".to_string(),
+            }),
+            ..msg.clone()
+        };
+        let exec_msg = ExecuteMsg::SubmitInvoice(snip20);
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            exec_msg,
+        );
+        assert!(res.is_err());
+
+        let exec_msg = ExecuteMsg::SubmitInvoice(msg);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            exec_msg,
+        )
+        .unwrap();
+
+        let accept_in_usdc = |deps: DepsMut| {
+            let info = mock_info(PAYER, &coins(27, "usdc"));
+            execute(deps, mock_env(), info, ExecuteMsg::AcceptInvoice { id: 1 })
+        };
+        let agree_funding_rate = |deps: DepsMut, rate: Decimal| {
+            let exec_msg = ExecuteMsg::AgreeFundingRate {
+                id: 1,
+                token: Token::Native("usdc".to_string()),
+                rate,
+            };
+            execute(deps, mock_env(), mock_info("anyone", &[]), exec_msg)
+        };
+
+        let exec_msg = ExecuteMsg::SetFundingRate {
+            id: 1,
+            token: Token::Native("usdc".to_string()),
+            rate: Decimal::percent(250),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(PAYER, &[]), exec_msg).unwrap();
+
+        // the rate must be agreed by the payee, at the rate the payer set
+        assert!(accept_in_usdc(deps.as_mut()).is_err());
+        assert!(agree_funding_rate(deps.as_mut(), Decimal::percent(300)).is_err());
+        agree_funding_rate(deps.as_mut(), Decimal::percent(250)).unwrap();
+
+        // 10 and its admin charge of 1 cost 25 and 2 in usdc
+        let res = accept_in_usdc(deps.as_mut()).unwrap();
+        assert_eq!(
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "creator".to_string(),
+                amount: coins(2, "usdc"),
+            }),
+            res.messages[0].msg
+        );
+
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::SingleContract {
+                id: 1,
+                payer: Addr::unchecked(PAYER),
+            },
+        )
+        .unwrap();
+        let value: Contract = from_binary(&res).unwrap();
+        assert_eq!(Uint128::new(10), value.invoice.pricing.unwrap().amount);
+        assert_eq!(Token::Native("usdc".to_string()), value.invoice.token);
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(2 * 86400);
+        let exec_msg = ExecuteMsg::WithdrawPayment { id: 1 };
+        let res = execute(deps.as_mut(), env, mock_info("anyone", &[]), exec_msg).unwrap();
+        assert_eq!(
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "anyone".to_string(),
+                amount: coins(23, "usdc"),
+            }),
+            res.messages[0].msg
+        );
+    }
+
+    #[test]
+    fn operator_delegation() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        submit(deps.as_mut(), "anyone", 3, None);
+
+        let info = mock_info(PAYER, &coins(10, "uscrt"));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::DepositTreasury {},
+        )
+        .unwrap();
+
+        let grant = |deps: DepsMut, permissions: Vec<OperatorPermission>| {
+            let exec_msg = ExecuteMsg::GrantOperator {
+                operator: "hr_wallet".to_string(),
+                permissions,
+                expires: None,
+            };
+            execute(deps, mock_env(), mock_info(PAYER, &[]), exec_msg)
+        };
+        let operate = |deps: DepsMut, exec_msg: ExecuteMsg, funds: &[Coin]| {
+            execute(deps, mock_env(), mock_info("hr_wallet", funds), exec_msg)
+        };
+
+        let accept_msg = ExecuteMsg::AcceptInvoice { id: 1 };
+        assert!(operate(deps.as_mut(), accept_msg.clone(), &[]).is_err());
+
+        grant(deps.as_mut(), vec![OperatorPermission::AcceptInvoice]).unwrap();
+
+        // the deposit comes from the payer treasury, not from the operator
+        assert!(operate(deps.as_mut(), accept_msg.clone(), &coins(4, "uscrt")).is_err());
+        operate(deps.as_mut(), accept_msg, &[]).unwrap();
+
+        let msg = QueryMsg::Treasury {
+            payer: Addr::unchecked(PAYER),
+        };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        assert_eq!(
+            Uint128::new(6),
+            from_binary::<TreasuryResponse>(&res).unwrap().balance
+        );
+
+        let cancel_msg = ExecuteMsg::CancelPayment { id: 1 };
+        assert!(operate(deps.as_mut(), cancel_msg.clone(), &[]).is_err());
+
+        // refunds still go to the payer
+        grant(deps.as_mut(), vec![OperatorPermission::CancelPayment]).unwrap();
+        let res = operate(deps.as_mut(), cancel_msg, &[]).unwrap();
+        assert_eq!(
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: PAYER.to_string(),
+                amount: coins(3, "uscrt"),
+            }),
+            res.messages[0].msg
+        );
+
+        let exec_msg = ExecuteMsg::RevokeOperator {
+            operator: "hr_wallet".to_string(),
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(PAYER, &[]),
+            exec_msg.clone(),
+        )
+        .unwrap();
+        assert!(execute(deps.as_mut(), mock_env(), mock_info(PAYER, &[]), exec_msg).is_err());
+    }
+
+    #[test]
+    fn organization_billing() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        let manage = |deps: DepsMut, sender: &str, exec_msg: ExecuteMsg| {
+            execute(deps, mock_env(), mock_info(sender, &[]), exec_msg)
+        };
+        let create_msg = ExecuteMsg::CreateOrganization {
+            name: "acme".to_string(),
+        };
+        manage(deps.as_mut(), "acme_owner", create_msg).unwrap();
+
+        let add_member = |deps: DepsMut, sender: &str, member: &str, admin: bool| {
+            let exec_msg = ExecuteMsg::AddOrganizationMember {
+                id: 1,
+                member: member.to_string(),
+                admin,
+            };
+            manage(deps, sender, exec_msg)
+        };
+        add_member(deps.as_mut(), "acme_owner", "finance_admin", true).unwrap();
+        add_member(deps.as_mut(), "finance_admin", "employee", false).unwrap();
+        assert!(add_member(deps.as_mut(), "finance_admin", "hr_admin", true).is_err());
+
+        let submit_to_organization = |deps: DepsMut, payee: &str| {
+            let exec_msg = ExecuteMsg::SubmitInvoice(SubmitInvoiceMsg {
+                purpose: "salary".to_string(),
+                amount: 3,
+                admin_charge: Uint128::new(1),
+                customer_charge: Uint128::new(1),
+                payer: None,
+                days: 2,
+                recurrent_time: None,
+                token: Token::Native("uscrt".to_string()),
+                late_payment: None,
+                early_payment: None,
+                milestones: None,
+                arbiter: None,
+                cancellation_policy: None,
+                expires_at: None,
+                valid_for_days: None,
+                payout_address: None,
+                funding_tokens: None,
+                organization: Some(1),
+                line_items: None,
+                memo: None,
+            });
+            manage(deps, payee, exec_msg)
+        };
+        submit_to_organization(deps.as_mut(), "employee").unwrap();
+
+        // any admin can accept, and pays the invoice from then on
+        let info = mock_info("finance_admin", &coins(4, "uscrt"));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::AcceptInvoice { id: 1 },
+        )
+        .unwrap();
+
+        for address in ["employee", "acme_owner"] {
+            key_auth(deps.as_mut(), address);
+        }
+        let auth = |address: &str| QueryAuth::ViewingKey {
+            key: format!("{}_key", address),
+        };
+
+        let msg = QueryMsg::Organization {
+            id: 1,
+            address: Addr::unchecked("employee"),
+            auth: auth("acme_owner"),
+        };
+        assert!(query(deps.as_ref(), mock_env(), msg).is_err());
+        let msg = QueryMsg::Organization {
+            id: 1,
+            address: Addr::unchecked("employee"),
+            auth: auth("employee"),
+        };
+        assert!(query(deps.as_ref(), mock_env(), msg).is_ok());
+
+        let contracts = |deps: Deps, address: &str| {
+            let msg = QueryMsg::OrganizationContracts {
+                id: 1,
+                address: Addr::unchecked(address),
+                page: 0,
+                page_size: 10,
+                auth: auth(address),
+            };
+            query(deps, mock_env(), msg)
+                .map(|res| from_binary::<OrganizationContractsResponse>(&res).unwrap())
+        };
+        assert!(contracts(deps.as_ref(), "employee").is_err());
+
+        let value = contracts(deps.as_ref(), "acme_owner").unwrap();
+        assert_eq!(1, value.total);
+        assert!(value.contracts[0].contract_accepted);
+        assert_eq!(
+            "finance_admin".to_string(),
+            value.contracts[0].invoice.payer
+        );
+
+        // an admin removed from the organization no longer pays its invoices
+        submit_to_organization(deps.as_mut(), "employee").unwrap();
+        let info = mock_info("finance_admin", &coins(4, "uscrt"));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::AcceptInvoice { id: 2 },
+        )
+        .unwrap();
+        add_member(deps.as_mut(), "acme_owner", "hr_admin", true).unwrap();
+        let remove_msg = ExecuteMsg::RemoveOrganizationMember {
+            id: 1,
+            member: "finance_admin".to_string(),
+        };
+        manage(deps.as_mut(), "acme_owner", remove_msg).unwrap();
+
+        let value = contracts(deps.as_ref(), "acme_owner").unwrap();
+        assert_eq!("acme_owner".to_string(), value.contracts[1].invoice.payer);
+        let cancel_msg = ExecuteMsg::CancelPayment { id: 2 };
+        assert!(manage(deps.as_mut(), "finance_admin", cancel_msg.clone()).is_err());
+
+        // any current admin pays for the organization
+        manage(deps.as_mut(), "hr_admin", cancel_msg).unwrap();
+
+        // only members can bill the organization
+        assert!(submit_to_organization(deps.as_mut(), "outsider").is_err());
+    }
+
+    #[test]
+    fn spend_by_tag() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        submit(deps.as_mut(), "anyone", 3, None);
+        submit(deps.as_mut(), "anyone", 3, None);
+
+        let set_tags = |deps: DepsMut, sender: &str| {
+            let exec_msg = ExecuteMsg::SetTags {
+                id: 1,
+                tags: vec![Tag {
+                    key: "department".to_string(),
+                    value: "engineering".to_string(),
+                }],
+            };
+            execute(deps, mock_env(), mock_info(sender, &[]), exec_msg)
+        };
+        assert!(set_tags(deps.as_mut(), "anyone").is_err());
+        set_tags(deps.as_mut(), PAYER).unwrap();
+
+        accept(deps.as_mut(), 1, 4).unwrap();
+        accept(deps.as_mut(), 2, 4).unwrap();
+        let exec_msg = ExecuteMsg::CancelPayment { id: 1 };
+        execute(deps.as_mut(), mock_env(), mock_info(PAYER, &[]), exec_msg).unwrap();
+
+        let wrong_key = QueryAuth::ViewingKey {
+            key: "wrong_key".to_string(),
+        };
+        let auth = key_auth(deps.as_mut(), PAYER);
+
+        let tags = |deps: Deps, auth: &QueryAuth| {
+            let msg = QueryMsg::Tags {
+                id: 1,
+                payer: Addr::unchecked(PAYER),
+                auth: auth.clone(),
+            };
+            query(deps, mock_env(), msg).map(|res| from_binary::<Vec<Tag>>(&res).unwrap())
+        };
+        assert!(tags(deps.as_ref(), &wrong_key).is_err());
+        assert_eq!(1, tags(deps.as_ref(), &auth).unwrap().len());
+
+        let spend_between = |deps: Deps, auth: &QueryAuth, from: u64| {
+            let msg = QueryMsg::SpendByTag {
+                payer: Addr::unchecked(PAYER),
+                tag: Tag {
+                    key: "department".to_string(),
+                    value: "engineering".to_string(),
+                },
+                from,
+                to: u64::MAX,
+                auth: auth.clone(),
+            };
+            query(deps, mock_env(), msg).map(|res| from_binary::<SpendByTagResponse>(&res).unwrap())
+        };
+        let spend = |deps: Deps, auth: &QueryAuth| spend_between(deps, auth, 0);
+        assert!(spend(deps.as_ref(), &wrong_key).is_err());
+
+        // only the tagged contract is counted
+        let value = spend(deps.as_ref(), &auth).unwrap();
+        assert_eq!(1, value.totals.len());
+        assert_eq!(Uint128::new(3), value.totals[0].escrowed);
+        assert_eq!(Uint128::zero(), value.totals[0].paid);
+        assert_eq!(Uint128::new(3), value.totals[0].refunded);
+
+        // nothing was spent after the window starts
+        let from = mock_env().block.time.seconds() + 1;
+        let value = spend_between(deps.as_ref(), &auth, from).unwrap();
+        assert!(value.totals.is_empty());
+
+        // spend stays counted under the tags the contract had at the time
+        let exec_msg = ExecuteMsg::SetTags {
+            id: 1,
+            tags: vec![],
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(PAYER, &[]), exec_msg).unwrap();
+        let value = spend(deps.as_ref(), &auth).unwrap();
+        assert_eq!(Uint128::new(3), value.totals[0].refunded);
+    }
+
+    #[test]
+    fn line_items_and_memo() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        let submit_items = |deps: DepsMut, amount: u128| {
+            let exec_msg = ExecuteMsg::SubmitInvoice(SubmitInvoiceMsg {
+                purpose: "building".to_string(),
+                amount,
+                admin_charge: Uint128::new(1),
+                customer_charge: Uint128::new(1),
+                payer: Some(PAYER.to_string()),
+                days: 2,
+                recurrent_time: None,
+                token: Token::Native("uscrt".to_string()),
+                late_payment: None,
+                early_payment: None,
+                milestones: None,
+                arbiter: None,
+                cancellation_policy: None,
+                expires_at: None,
+                valid_for_days: None,
+                payout_address: None,
+                funding_tokens: None,
+                organization: None,
+                line_items: Some(vec![
+                    LineItem {
+                        description: "design".to_string(),
+                        quantity: 2,
+                        unit_price: Uint128::new(4),
+                        tax: Uint128::new(1),
+                    },
+                    LineItem {
+                        description: "review".to_string(),
+                        quantity: 1,
+                        unit_price: Uint128::new(1),
+                        tax: Uint128::zero(),
+                    },
+                ]),
+                memo: Some("PO 1234".to_string()),
+            });
+            execute(deps, mock_env(), mock_info("anyone", &[]), exec_msg)
+        };
+        submit_items(deps.as_mut(), 10).unwrap();
+
+        let memo = |deps: Deps, address: &str, auth: QueryAuth| {
+            let msg = QueryMsg::Memo {
+                id: 1,
+                address: Addr::unchecked(address),
+                auth,
+            };
+            query(deps, mock_env(), msg).map(|res| from_binary::<MemoResponse>(&res).unwrap())
+        };
+        let wrong_key = QueryAuth::ViewingKey {
+            key: "wrong_key".to_string(),
+        };
+        assert!(memo(deps.as_ref(), PAYER, wrong_key).is_err());
+        let auth = key_auth(deps.as_mut(), "stranger");
+        assert!(memo(deps.as_ref(), "stranger", auth).is_err());
+        let auth = key_auth(deps.as_mut(), PAYER);
+        let res = memo(deps.as_ref(), PAYER, auth.clone()).unwrap();
+        assert_eq!(Some("PO 1234".to_string()), res.memo);
+        assert_eq!(2, res.line_items.len());
+        assert_eq!("design", res.line_items[0].description);
+
+        // the line items are kept out of the public invoice
+        let msg = QueryMsg::SingleInvoice {
+            id: 1,
+            owner: Addr::unchecked("anyone"),
+        };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        assert!(!String::from_utf8(res.to_vec()).unwrap().contains("design"));
+
+        let exec_msg = ExecuteMsg::SetMemo {
+            id: 1,
+            memo: Some("paid by wire".to_string()),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(PAYER, &[]), exec_msg).unwrap();
+        assert_eq!(
+            Some("paid by wire".to_string()),
+            memo(deps.as_ref(), PAYER, auth).unwrap().memo
+        );
+
+        // the line items must add up to the amount
+        assert!(submit_items(deps.as_mut(), 11).is_err());
+    }
+
+    #[test]
+    fn withholding() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        submit(deps.as_mut(), "anyone", 10, None);
+
+        let withholding = |basis_points: u64| Withholding {
+            basis_points,
+            authority: "tax_office".to_string(),
+            agreed: false,
+        };
+        let set_withholding = |deps: DepsMut, sender: &str, id: u64| {
+            let exec_msg = ExecuteMsg::SetWithholding {
+                id,
+                withholding: Some(withholding(2500)),
+            };
+            execute(deps, mock_env(), mock_info(sender, &[]), exec_msg)
+        };
+        let agree_withholding = |deps: DepsMut, id: u64, basis_points: u64| {
+            let exec_msg = ExecuteMsg::AgreeWithholding {
+                id,
+                withholding: withholding(basis_points),
+            };
+            execute(deps, mock_env(), mock_info("anyone", &[]), exec_msg)
+        };
+        assert!(set_withholding(deps.as_mut(), "anyone", 1).is_err());
+        set_withholding(deps.as_mut(), PAYER, 1).unwrap();
+
+        // the payee has to agree to the withholding they were shown
+        assert!(accept(deps.as_mut(), 1, 11).is_err());
+        assert!(agree_withholding(deps.as_mut(), 1, 5000).is_err());
+        agree_withholding(deps.as_mut(), 1, 2500).unwrap();
+        accept(deps.as_mut(), 1, 11).unwrap();
+
+        // the payer can not change the withholding once the payee is bound
+        assert!(set_withholding(deps.as_mut(), PAYER, 1).is_err());
+
+        // a quarter of the net payment of 9 goes to the tax authority
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(2 * 86400);
+        let exec_msg = ExecuteMsg::WithdrawPayment { id: 1 };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            exec_msg,
+        )
+        .unwrap();
+        assert_eq!(
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "anyone".to_string(),
+                amount: coins(7, "uscrt"),
+            }),
+            res.messages[0].msg
+        );
+        assert_eq!(
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "tax_office".to_string(),
+                amount: coins(2, "uscrt"),
+            }),
+            res.messages[1].msg
+        );
+
+        let auth = key_auth(deps.as_mut(), "anyone");
+        let res = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::PayeeStatement {
+                payee: Addr::unchecked("anyone"),
+                from: 0,
+                to: env.block.time.seconds(),
+                token: Token::Native("uscrt".to_string()),
+                page: 0,
+                auth,
+            },
+        )
+        .unwrap();
+        let value: PayeeStatementResponse = from_binary(&res).unwrap();
+        assert_eq!(Uint128::new(2), value.total_withheld);
+        assert_eq!(Uint128::new(7), value.total_net_amount);
+
+        // tax is withheld from the payment due when the payee resigns too
+        submit(deps.as_mut(), "anyone", 10, Some(2));
+        set_withholding(deps.as_mut(), PAYER, 2).unwrap();
+        agree_withholding(deps.as_mut(), 2, 2500).unwrap();
+        accept(deps.as_mut(), 2, 22).unwrap();
+
+        env.block.time = env.block.time.plus_seconds(86400);
+        let exec_msg = ExecuteMsg::Resign { id: 2 };
+        let res = execute(deps.as_mut(), env, mock_info("anyone", &[]), exec_msg).unwrap();
+        assert_eq!(
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "anyone".to_string(),
+                amount: coins(7, "uscrt"),
+            }),
+            res.messages[0].msg
+        );
+        assert_eq!(
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "tax_office".to_string(),
+                amount: coins(2, "uscrt"),
+            }),
+            res.messages[1].msg
+        );
+    }
+
+    #[test]
+    fn payroll_report() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+
+        submit(deps.as_mut(), "anyone", 10, None);
+        let withholding = Withholding {
+            basis_points: 2500,
+            authority: "tax_office".to_string(),
+            agreed: false,
+        };
+        let exec_msg = ExecuteMsg::SetWithholding {
+            id: 1,
+            withholding: Some(withholding.clone()),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(PAYER, &[]), exec_msg).unwrap();
+        let exec_msg = ExecuteMsg::AgreeWithholding { id: 1, withholding };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            exec_msg,
+        )
+        .unwrap();
+        accept(deps.as_mut(), 1, 11).unwrap();
+
+        let exec_msg = ExecuteMsg::SetViewingKey {
+            key: "payer_key".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(PAYER, &[]), exec_msg).unwrap();
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(2 * 86400);
+        let exec_msg = ExecuteMsg::WithdrawPayment { id: 1 };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            exec_msg,
+        )
+        .unwrap();
+
+        let report = |deps: Deps, key: &str, from: u64| {
+            let msg = QueryMsg::PayrollReport {
+                payer: Addr::unchecked(PAYER),
+                from,
+                to: env.block.time.seconds(),
+                page: 0,
+                auth: QueryAuth::ViewingKey {
+                    key: key.to_string(),
+                },
+            };
+            query(deps, env.clone(), msg)
+                .map(|res| from_binary::<PayrollReportResponse>(&res).unwrap())
+        };
+        assert!(report(deps.as_ref(), "wrong_key", 0).is_err());
+
+        let value = report(deps.as_ref(), "payer_key", 0).unwrap();
+        let rows: Vec<(PayrollRowKind, u128)> = value
+            .rows
+            .iter()
+            .map(|row| (row.kind, row.amount.u128()))
+            .collect();
+        assert_eq!(
+            vec![
+                (PayrollRowKind::Deposit, 10),
+                (PayrollRowKind::Fee, 1),
+                (PayrollRowKind::Payout, 7),
+                (PayrollRowKind::Fee, 1),
+                (PayrollRowKind::Tax, 2),
+            ],
+            rows
+        );
+        assert_eq!(5, value.total);
+        assert_eq!(MOCK_CONTRACT_ADDR, value.rows[0].counterparty);
+        assert_eq!("anyone", value.rows[2].counterparty);
+        assert_eq!("tax_office", value.rows[4].counterparty);
+        assert_eq!(env.block.time.seconds(), value.rows[2].time);
+
+        // only the rows of the window are reported
+        let value = report(deps.as_ref(), "payer_key", env.block.time.seconds()).unwrap();
+        assert_eq!(3, value.total);
+        assert_eq!(PayrollRowKind::Payout, value.rows[0].kind);
+
+        let exec_msg = ExecuteMsg::RevokePermit {
+            permit_name: "payroll".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(PAYER, &[]), exec_msg).unwrap();
+        assert!(RevokedPermits::is_permit_revoked(
+            deps.as_ref().storage,
+            PREFIX_REVOKED_PERMITS,
+            PAYER,
+            "payroll"
+        ));
+    }
+
+    #[test]
+    fn legacy_contract_loads() {
+        // a contract as stored before the fields added since
+        let stored = br#"{
+            "invoice_id": 1,
+            "account_balance": "9",
+            "contract_process": "started",
+            "invoice": {
+                "invoice_id": 1,
+                "receiver": "anyone",
+                "purpose": "building",
+                "amount": "3",
+                "admin_charges": "1",
+                "customer_charges": "1",
+                "payer": "payer",
+                "days": 2,
+                "recurrent": true,
+                "recurrent_times": 3,
+                "remaining_time_of_payment": 3,
+                "status": "started",
+                "payment_time": 0,
+                "critical_time": 0,
+                "payment_condition": "",
+                "token": { "native": "uscrt" }
+            },
+            "contract_accepted": true
+        }"#;
+        let contract = from_slice::<Contract>(stored).unwrap().upgraded();
+        assert_eq!(3, contract.funded_periods);
+        assert!(contract.next_payment_funded());
+        assert!(contract.amendments.is_empty());
+        assert_eq!(None, contract.invoice.withholding);
+    }
+
+    /// Stores invoice 1 the way versions before the parties were kept did.
+    fn store_legacy(deps: DepsMut, accepted: bool) {
+        let (status, condition, process, balance) = match accepted {
+            true => ("accepted", "pay full", "started", 9),
+            false => ("not started", "no", "not started", 0),
+        };
+        let stored = format!(
+            r#"{{
+                "invoice_id": 1,
+                "account_balance": "{}",
+                "contract_process": "{}",
+                "invoice": {{
+                "invoice_id": 1,
+                "receiver": "anyone",
+                "purpose": "building",
+                "amount": "3",
+                "admin_charges": "1",
+                "customer_charges": "1",
+                "payer": "{}",
+                "days": 2,
+                "recurrent": true,
+                "recurrent_times": 3,
+                "remaining_time_of_payment": 3,
+                "status": "{}",
+                "payment_time": 0,
+                "critical_time": 0,
+                "payment_condition": "{}",
+                "token": {{ "native": "uscrt" }}
+                }},
+                "contract_accepted": {}
+            }}"#,
+            balance, process, PAYER, status, condition, accepted
+        );
+        let contract: Contract = from_slice(stored.as_bytes()).unwrap();
+
+        INVOICE
+            .add_suffix(b"anyone")
+            .insert(deps.storage, &1, &contract.invoice)
+            .unwrap();
+        CONTRACT
+            .add_suffix(PAYER.as_bytes())
+            .insert(deps.storage, &1, &contract)
+            .unwrap();
+    }
+
+    #[test]
+    fn legacy_invoice_payments() {
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+        store_legacy(deps.as_mut(), true);
+
+        // the payee of a contract stored without its parties is still paid
+        let exec_msg = ExecuteMsg::WithdrawPayment { id: 1 };
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            exec_msg,
+        )
+        .unwrap();
+        assert_eq!(
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "anyone".to_string(),
+                amount: coins(2, "uscrt"),
+            }),
+            res.messages[0].msg
+        );
+
+        // and the payer of one still accepts and cancels it
+        let mut deps = mock_dependencies();
+        init_contract(deps.as_mut());
+        store_legacy(deps.as_mut(), false);
+
+        accept(deps.as_mut(), 1, 12).unwrap();
+
+        // it is indexed for the listings once saved again
+        let msg = QueryMsg::FilteredContract {
+            payer: Addr::unchecked(PAYER),
+            filter: None,
+            sort_by: None,
+            descending: None,
+            start_after: None,
+            limit: None,
+        };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        let value: ContractListResponse = from_binary(&res).unwrap();
+        assert_eq!(1, value.contracts.len());
+
+        let exec_msg = ExecuteMsg::CancelPayment { id: 1 };
+        let res = execute(deps.as_mut(), mock_env(), mock_info(PAYER, &[]), exec_msg).unwrap();
+        assert_eq!(Some("cancel_payment"), attribute(&res, "action"));
+    }
 }
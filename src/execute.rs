@@ -1,32 +1,555 @@
 use cosmwasm_std::{
-    coins, BankMsg, CosmosMsg, DepsMut, Env, MessageInfo, Response, StdError, StdResult, Uint128,
+    coins, Addr, Api, BankMsg, Coin, Decimal, DepsMut, Env, MessageInfo, Response, StdError,
+    StdResult, Storage, Uint128,
 };
 
+use secret_toolkit::permit::RevokedPermits;
 use secret_toolkit::utils::types::Token;
+use secret_toolkit::viewing_key::{ViewingKey, ViewingKeyStore};
+
+use crate::msg::SubmitInvoiceMsg;
+use crate::state::{
+    get_next_invoice_id, get_next_organization_id, AdminStore, Amendment, ArbiterStore,
+    CancellationPolicy, Contract, ContractStore, Dispute, DisputeResolution, DisputeStore,
+    EarlyPaymentTerms, ExpiryStore, FundingToken, HistoryStore, Invoice, InvoiceEvent,
+    InvoiceEventKind, InvoiceParties, InvoiceStatus, InvoiceStore, LateFee, LatePaymentTerms,
+    LineItemStore, ListingIndex, MemoStore, Milestone, MilestoneStatus, Operator,
+    OperatorPermission, OperatorStore, Organization, OrganizationStore, PartiesStore, PayoutShare,
+    PayrollRow, PayrollRowKind, PayrollStore, Tag, TagSpend, TagSpendStore, TagStore,
+    TreasuryStore, Withdrawal, WithdrawalStore, Withholding, PAYER_ROLE, PREFIX_REVOKED_PERMITS,
+    TREASURY_DENOM,
+};
+
+/// Most tags an invoice can carry.
+const MAX_TAGS: usize = 10;
+
+/// Transfer of `amount` to `to_address`, left out when there is nothing to send.
+fn bank_send(to_address: &Addr, amount: u128, denom: &str) -> Option<BankMsg> {
+    if amount == 0 {
+        return None;
+    }
+
+    Some(BankMsg::Send {
+        to_address: to_address.to_string(),
+        amount: coins(amount, denom),
+    })
+}
+
+/// Payer of invoice `id`, if the sender is the payer or one of their
+/// operators with `permission`. Invoices billed to an organization are
+/// paid by whoever is one of its admins now.
+fn acting_payer(
+    deps: &DepsMut,
+    env: &Env,
+    sender: &Addr,
+    id: u64,
+    permission: OperatorPermission,
+) -> StdResult<Addr> {
+    let parties = PartiesStore::load_for(deps.storage, id, sender)?;
+    let payer = deps.api.addr_validate(parties.payer.as_str())?;
+    let now = env.block.time.seconds();
+
+    let contract = ContractStore::load_contract(deps.storage, &payer, id);
+    if let Some(organization) = contract.invoice.organization {
+        let organization = OrganizationStore::load(deps.storage, organization)?;
+
+        if organization.is_admin(sender) {
+            return Ok(payer);
+        }
+
+        if !organization.is_admin(&payer) {
+            return Err(StdError::generic_err(
+                "The payer is no longer an admin of the organization billed",
+            ));
+        }
+    }
+
+    if payer != *sender
+        && !OperatorStore::is_authorized(deps.storage, &payer, sender, permission, now)
+    {
+        return Err(StdError::generic_err(
+            "You are not the payer or an operator of the payer for this invoice",
+        ));
+    }
+
+    Ok(payer)
+}
+
+/// Moves an invoice billed to an organization under `admin`, one of its
+/// admins, who pays it from then on.
+fn claim_for_organization(deps: &mut DepsMut, admin: &Addr, id: u64) -> StdResult<()> {
+    let parties = PartiesStore::load_for(deps.storage, id, admin)?;
+    let payer = deps.api.addr_validate(parties.payer.as_str())?;
+
+    if payer == *admin {
+        return Ok(());
+    }
+
+    let contract = ContractStore::load_contract(deps.storage, &payer, id);
+
+    let organization = match contract.invoice.organization {
+        Some(organization) => OrganizationStore::load(deps.storage, organization)?,
+        None => return Ok(()),
+    };
+
+    if contract.contract_accepted || !organization.is_admin(admin) {
+        return Ok(());
+    }
+
+    move_to_payer(deps, &payer, admin, id)
+}
+
+/// Moves invoice `id` from `payer` to `new_payer`, who pays it from then on.
+fn move_to_payer(deps: &mut DepsMut, payer: &Addr, new_payer: &Addr, id: u64) -> StdResult<()> {
+    let mut parties = PartiesStore::load_for(deps.storage, id, payer)?;
+    let mut contract = ContractStore::load_contract(deps.storage, payer, id);
+
+    let receiver = deps.api.addr_validate(parties.payee.as_str())?;
+
+    contract.invoice.payer = new_payer.to_string();
+    parties.payer = new_payer.to_string();
+
+    ContractStore::remove(deps.storage, payer, id)?;
+    InvoiceStore::save(deps.storage, &receiver, id, &contract.invoice)?;
+    ContractStore::save(deps.storage, new_payer, id, &contract)?;
+    PartiesStore::save(deps.storage, id, &parties)
+}
+
+/// Hands the open invoices of `organization` that `admin` pays over to its
+/// owner, once `admin` is no longer one of its admins.
+fn release_organization_invoices(
+    deps: &mut DepsMut,
+    organization: &Organization,
+    admin: &Addr,
+) -> StdResult<()> {
+    let owner = deps.api.addr_validate(organization.owner.as_str())?;
+
+    for status in [InvoiceStatus::Pending, InvoiceStatus::Active] {
+        for id in ListingIndex::ids(deps.storage, PAYER_ROLE, admin, status)? {
+            let contract = ContractStore::load_contract(deps.storage, admin, id);
+
+            if contract.invoice.organization == Some(organization.id) {
+                move_to_payer(deps, admin, &owner, id)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Deposit an operator makes for the payer. Operators can not attach funds,
+/// `needed` is drawn from the payer treasury instead.
+fn draw_for_operator(
+    storage: &mut dyn Storage,
+    funds: &[Coin],
+    payer: &Addr,
+    denom: &str,
+    needed: Uint128,
+) -> StdResult<Uint128> {
+    if !funds.is_empty() {
+        return Err(StdError::generic_err(
+            "Operators can not attach funds, the deposit is drawn from the payer treasury",
+        ));
+    }
+
+    let treasury_balance = TreasuryStore::available(storage, payer, denom);
+    if treasury_balance < needed {
+        let error_message = format!(
+            "Payer treasury holds {} of the {} needed",
+            treasury_balance, needed
+        );
+
+        return Err(StdError::generic_err(error_message));
+    }
+
+    TreasuryStore::save(storage, payer, treasury_balance - needed)?;
+    Ok(needed)
+}
+
+/// Transfer of the tax withheld on a payment of `invoice` to its tax authority.
+fn withholding_send(
+    api: &dyn Api,
+    invoice: &Invoice,
+    withheld: Uint128,
+    denom: &str,
+) -> StdResult<Option<BankMsg>> {
+    match &invoice.withholding {
+        Some(withholding) => {
+            let authority = api.addr_validate(withholding.authority.as_str())?;
+            Ok(bank_send(&authority, withheld.u128(), denom))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Keeps `event` in the history of invoice `id`, and the funds it moves in the payroll of
+/// its payer.
+fn push_event(
+    storage: &mut dyn Storage,
+    env: &Env,
+    id: u64,
+    event: &InvoiceEvent,
+) -> StdResult<()> {
+    HistoryStore::push(storage, id, event)?;
+
+    let kind = match event.kind {
+        InvoiceEventKind::Deposited => PayrollRowKind::Deposit,
+        InvoiceEventKind::Refunded => PayrollRowKind::Refund,
+        InvoiceEventKind::Withdrawn
+        | InvoiceEventKind::MilestoneApproved
+        | InvoiceEventKind::DisputeResolved
+        | InvoiceEventKind::PenaltyPaid => PayrollRowKind::Payout,
+        _ => return Ok(()),
+    };
+
+    // invoices stored before the parties were kept have no payroll to record it in
+    let parties = match PartiesStore::may_load(storage, id) {
+        Some(parties) => parties,
+        None => return Ok(()),
+    };
 
-use crate::state::{get_next_invoice_id, AdminStore, Contract, ContractStore, Invoice, InvoiceStore};
+    // deposits and refunds move funds between the payer and the escrow, deposits carry the
+    // admin charge and payouts the customer charge and the withheld tax
+    let (counterparty, amount) = match kind {
+        PayrollRowKind::Payout => (
+            parties.payee,
+            event.amount.saturating_sub(event.fee + event.withheld),
+        ),
+        _ => (env.contract.address.to_string(), event.amount),
+    };
+
+    let row = PayrollRow {
+        invoice_id: id,
+        kind,
+        counterparty,
+        token: event.token.clone(),
+        amount,
+        block_height: event.block_height,
+        time: event.time,
+    };
+    PayrollStore::push(storage, &parties.payer, &row)?;
+
+    // the payer spend on each tag the invoice carries at the time
+    let zero = Uint128::zero();
+    let (escrowed, paid, refunded) = match kind {
+        PayrollRowKind::Deposit => (event.amount, zero, zero),
+        PayrollRowKind::Refund => (zero, zero, event.amount),
+        _ => (zero, event.amount, zero),
+    };
+    let spend = TagSpend {
+        token: event.token.clone(),
+        escrowed,
+        paid,
+        refunded,
+    };
+    for tag in TagStore::load(storage, id) {
+        TagSpendStore::add(storage, &parties.payer, &tag, event.time, &spend)?;
+    }
+
+    if !event.fee.is_zero() {
+        let fee = PayrollRow {
+            kind: PayrollRowKind::Fee,
+            counterparty: AdminStore::get_admin_wallet(storage),
+            amount: event.fee,
+            ..row.clone()
+        };
+        PayrollStore::push(storage, &parties.payer, &fee)?;
+    }
+
+    if !event.withheld.is_zero() {
+        let payer = Addr::unchecked(parties.payer.as_str());
+        let contract = ContractStore::load_contract(storage, &payer, id);
+
+        if let Some(withholding) = contract.invoice.withholding {
+            let tax = PayrollRow {
+                kind: PayrollRowKind::Tax,
+                counterparty: withholding.authority,
+                amount: event.withheld,
+                ..row
+            };
+            PayrollStore::push(storage, &parties.payer, &tax)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Pays `gross` out of the escrow of `contract` to its payee, less the `fee` for the admin
+/// wallet and the tax withheld for the tax authority. The payment is kept in the payee
+/// statement and in the history as `kind` by `actor`, and its transfers are returned.
+fn pay_out(
+    deps: &mut DepsMut,
+    env: &Env,
+    contract: &Contract,
+    gross: Uint128,
+    fee: Uint128,
+    kind: InvoiceEventKind,
+    actor: &Addr,
+) -> StdResult<Vec<BankMsg>> {
+    let invoice = &contract.invoice;
+    let id = contract.invoice_id;
+    let denom = invoice.denom()?;
+
+    let receiver = deps.api.addr_validate(invoice.receiver.as_str())?;
+    let admin_wallet = AdminStore::get_admin_wallet(deps.storage);
+    let admin_wallet = deps.api.addr_validate(admin_wallet.as_str())?;
+
+    // income tax is withheld from the payee share for the tax authority
+    let withheld = invoice.withheld_from(gross - fee);
+    let payee_payment = gross - fee - withheld;
+
+    if !gross.is_zero() {
+        // keep the payment in the payee statement
+        WithdrawalStore::push(
+            deps.storage,
+            &receiver,
+            &Withdrawal {
+                invoice_id: id,
+                time: env.block.time.seconds(),
+                gross_amount: gross,
+                fee,
+                withheld,
+                net_amount: payee_payment,
+                token: invoice.token.clone(),
+            },
+        )?;
+    }
+    push_event(
+        deps.storage,
+        env,
+        id,
+        &InvoiceEvent::new(kind, actor, gross, fee, &invoice.token, env).with_withheld(withheld),
+    )?;
+
+    let mut messages = Payout::of(deps.api, invoice)?.messages(payee_payment.u128(), &denom);
+    messages.extend(withholding_send(deps.api, invoice, withheld, &denom)?);
+    messages.extend(bank_send(&admin_wallet, fee.u128(), &denom));
+
+    Ok(messages)
+}
+
+/// Funds attached in `denom`, rejecting any other token.
+fn deposit_of(funds: &[Coin], denom: &str) -> StdResult<Uint128> {
+    let mut amount = Uint128::zero();
+    for coin in funds {
+        if coin.denom != denom {
+            let error_message = format!("Token {} is not accepted for this invoice", coin.denom);
+
+            return Err(StdError::generic_err(error_message));
+        }
+
+        amount += coin.amount
+    }
+
+    Ok(amount)
+}
+
+/// Number of payments of `invoice` a deposit pays for, up to `unfunded`,
+/// and the admin charges taken on them.
+fn periods_paid_by(invoice: &Invoice, deposit: Uint128, unfunded: u64) -> (u64, Uint128) {
+    let period_cost = invoice.amount + invoice.admin_charges;
+    let periods = (deposit / period_cost).u128().min(unfunded.into()) as u64;
+
+    (periods, invoice.admin_charges * Uint128::from(periods))
+}
+
+/// Where the payee of an invoice is paid: the shares of their payout split,
+/// or else their payout address, or else the submitter.
+struct Payout(Vec<(Addr, u64)>);
+
+impl Payout {
+    fn of(api: &dyn Api, invoice: &Invoice) -> StdResult<Self> {
+        if invoice.payout_split.is_empty() {
+            let address = invoice
+                .payout_address
+                .as_deref()
+                .unwrap_or(invoice.receiver.as_str());
 
-pub struct Empty {}
+            return Ok(Payout(vec![(api.addr_validate(address)?, 10000)]));
+        }
+
+        let mut shares = vec![];
+        for share in &invoice.payout_split {
+            shares.push((api.addr_validate(share.address.as_str())?, share.basis_points));
+        }
+
+        Ok(Payout(shares))
+    }
+
+    /// Transfers paying `amount` to the payee, the last share taking the
+    /// rounding remainder so that the shares add up to `amount`.
+    fn messages(&self, amount: u128, denom: &str) -> Vec<BankMsg> {
+        let mut left = amount;
+        let mut messages = vec![];
+
+        for (index, (address, basis_points)) in self.0.iter().enumerate() {
+            let share = match index == self.0.len() - 1 {
+                true => left,
+                false => Uint128::from(amount)
+                    .multiply_ratio(*basis_points, 10000u64)
+                    .u128(),
+            };
+            left -= share;
+
+            messages.extend(bank_send(address, share, denom));
+        }
+
+        messages
+    }
+}
+
+/// Takes the late penalty owed to the payee out of `amount`, as far as it goes.
+fn take_penalty(accrued_penalty: &mut Uint128, amount: Uint128) -> Uint128 {
+    let penalty = (*accrued_penalty).min(amount);
+    *accrued_penalty -= penalty;
+
+    penalty
+}
+
+/// Accrues the late penalty of the payer contracts drawing from the treasury,
+/// overdue unless `covered` pays their next payment, and pays what they owe
+/// out of `balance`. Returns the penalties paid with their transfers.
+fn settle_treasury_penalties(
+    deps: &mut DepsMut,
+    env: &Env,
+    payer: &Addr,
+    covered: Uint128,
+    balance: Uint128,
+) -> StdResult<(Uint128, Vec<BankMsg>)> {
+    let mut balance = balance;
+    let mut penalties = Uint128::zero();
+    let mut messages = vec![];
+
+    for id in ListingIndex::ids(deps.storage, PAYER_ROLE, payer, InvoiceStatus::Active)? {
+        let mut contract = ContractStore::load_contract(deps.storage, payer, id);
+
+        match &contract.invoice.token {
+            Token::Native(denom) if denom == TREASURY_DENOM => {}
+            _ => continue,
+        }
+
+        contract.accrue_penalty(env.block.time.seconds(), covered);
+        let penalty = take_penalty(&mut contract.accrued_penalty, balance);
+
+        if !penalty.is_zero() {
+            let payout = Payout::of(deps.api, &contract.invoice)?;
+            messages.extend(payout.messages(penalty.u128(), TREASURY_DENOM));
+
+            push_event(
+                deps.storage,
+                env,
+                id,
+                &InvoiceEvent::new(
+                    InvoiceEventKind::PenaltyPaid,
+                    payer,
+                    penalty,
+                    Uint128::zero(),
+                    &contract.invoice.token,
+                    env,
+                ),
+            )?;
+
+            balance -= penalty;
+            penalties += penalty;
+        }
+
+        ContractStore::save(deps.storage, payer, id, &contract)?;
+    }
+
+    Ok((penalties, messages))
+}
+
+/// Denom or contract address of a token, for response attributes.
+fn token_label(token: &Token) -> String {
+    match token {
+        Token::Native(denom) => denom.clone(),
+        Token::Snip20(contract) => contract.address.clone(),
+    }
+}
 
-#[allow(clippy::too_many_arguments)]
 pub fn new_invoice(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     info: MessageInfo,
-    purpose: String,
-    amount: u128,
-    admin_charge: Uint128,
-    customer_charge: Uint128,
-    payer: String,
-    days: u64,
-    recurrent_time: Option<u64>,
-    token: Token,
+    msg: SubmitInvoiceMsg,
 ) -> StdResult<Response> {
+    let SubmitInvoiceMsg {
+        purpose,
+        amount,
+        admin_charge,
+        customer_charge,
+        payer,
+        days,
+        recurrent_time,
+        token,
+        late_payment,
+        early_payment,
+        milestones,
+        arbiter,
+        cancellation_policy,
+        expires_at,
+        valid_for_days,
+        payout_address,
+        funding_tokens,
+        organization,
+        line_items,
+        memo,
+    } = msg;
+
     // get the signer
     let receiver = info.sender;
 
-    // validate payer address
-    let payer_address = deps.api.addr_validate(payer.as_str())?;
+    // validate the address the payee is paid at
+    let payout_address = match payout_address {
+        Some(address) => Some(deps.api.addr_validate(address.as_str())?.to_string()),
+        None => None,
+    };
+
+    // an organization is billed by its members, and paid by its admins
+    let payer_address = match organization {
+        Some(id) => {
+            let organization = OrganizationStore::load(deps.storage, id)?;
+
+            if !organization.is_member(&receiver) {
+                return Err(StdError::generic_err(
+                    "You are not a member of this organization",
+                ));
+            }
+
+            // the owner holds the invoice until an admin accepts it
+            let payer = payer.unwrap_or_else(|| organization.owner.clone());
+            let payer_address = deps.api.addr_validate(payer.as_str())?;
+
+            if !organization.is_admin(&payer_address) {
+                return Err(StdError::generic_err(
+                    "Payer must be an admin of the organization billed",
+                ));
+            }
+
+            payer_address
+        }
+        None => match payer {
+            Some(payer) => deps.api.addr_validate(payer.as_str())?,
+            None => {
+                return Err(StdError::generic_err(
+                    "Payer is required unless an organization is billed",
+                ))
+            }
+        },
+    };
+
+    // validate the arbiter chosen for this invoice
+    let arbiter = match arbiter {
+        Some(arbiter) => Some(deps.api.addr_validate(arbiter.as_str())?.to_string()),
+        None => None,
+    };
+
+    // payments are settled with bank messages, so only native tokens can be invoiced
+    if let Token::Snip20(_) = token {
+        return Err(StdError::generic_err(
+            "SNIP-20 tokens are not supported, invoice in a native token",
+        ));
+    }
 
     // get next invoice id
     let next_invoice_id = get_next_invoice_id(deps.storage)?;
@@ -57,6 +580,145 @@ pub fn new_invoice(
         ));
     }
 
+    if let Some(LatePaymentTerms {
+        late_fee: LateFee::Percentage { basis_points },
+        ..
+    }) = &late_payment
+    {
+        if *basis_points > 10000 {
+            return Err(StdError::generic_err(
+                "late fee percentage can not exceed 10000 basis points",
+            ));
+        }
+    }
+
+    if let Some(EarlyPaymentTerms { basis_points, .. }) = &early_payment {
+        if *basis_points > 10000 {
+            return Err(StdError::generic_err(
+                "discount percentage can not exceed 10000 basis points",
+            ));
+        }
+
+        // the charge on payee is taken out of each discounted payment
+        let amount = Uint128::from(amount);
+        if amount - amount.multiply_ratio(*basis_points, 10000u64) < customer_charge {
+            return Err(StdError::generic_err(
+                "discount can not drop the amount below the charge on payee",
+            ));
+        }
+    }
+
+    if let Some(CancellationPolicy {
+        kill_fee_basis_points,
+        ..
+    }) = &cancellation_policy
+    {
+        if *kill_fee_basis_points > 10000 {
+            return Err(StdError::generic_err(
+                "kill fee percentage can not exceed 10000 basis points",
+            ));
+        }
+    }
+
+    let current_block_time = env.block.time.seconds();
+
+    let expires_at = match (expires_at, valid_for_days) {
+        (Some(_), Some(_)) => {
+            return Err(StdError::generic_err(
+                "Set either expires_at or valid_for_days, not both",
+            ))
+        }
+        (Some(expires_at), None) => Some(expires_at),
+        (None, Some(days)) => Some(current_block_time + days * 86400),
+        (None, None) => None,
+    };
+
+    if let Some(expires_at) = expires_at {
+        if expires_at <= current_block_time {
+            return Err(StdError::generic_err("Invoice would already be expired"));
+        }
+    }
+
+    let mut review_period_days = 0;
+    let mut invoice_milestones = vec![];
+
+    if let Some(plan) = milestones {
+        if plan.milestones.is_empty() {
+            return Err(StdError::generic_err("Milestone list can not be empty"));
+        }
+
+        if recurrent_time.is_some() || early_payment.is_some() {
+            return Err(StdError::generic_err(
+                "Milestone invoices can not be recurrent or discounted",
+            ));
+        }
+
+        let total: Uint128 = plan.milestones.iter().map(|milestone| milestone.amount).sum();
+        if total != Uint128::from(amount) {
+            let error_message = format!(
+                "Milestone amounts add up to {} instead of the invoice amount {}",
+                total, amount
+            );
+
+            return Err(StdError::generic_err(error_message));
+        }
+
+        review_period_days = plan.review_period_days;
+        invoice_milestones = plan
+            .milestones
+            .into_iter()
+            .map(|terms| Milestone {
+                description: terms.description,
+                amount: terms.amount,
+                due_date: terms.due_date,
+                status: MilestoneStatus::Pending,
+                proof: None,
+                submitted_at: None,
+            })
+            .collect();
+    }
+
+    // other tokens the payee accepts, their rates are set once the payer agrees to pay in them
+    let mut accepted_tokens: Vec<FundingToken> = vec![];
+    for funding_token in funding_tokens.unwrap_or_default() {
+        let listed = accepted_tokens
+            .iter()
+            .any(|accepted| accepted.token == funding_token);
+
+        match &funding_token {
+            Token::Native(_) if funding_token != token && !listed => {}
+            _ => {
+                return Err(StdError::generic_err(
+                    "Funding tokens must be distinct native tokens other than the invoice token",
+                ))
+            }
+        }
+
+        accepted_tokens.push(FundingToken {
+            token: funding_token,
+            rate: None,
+            agreed: false,
+        });
+    }
+
+    // line items break down the amount of each payment
+    let line_items = line_items.unwrap_or_default();
+    if !line_items.is_empty() {
+        if line_items.iter().any(|item| item.quantity == 0) {
+            return Err(StdError::generic_err("Line item quantity must be positive"));
+        }
+
+        let total: Uint128 = line_items.iter().map(|item| item.total()).sum();
+        if total != Uint128::from(amount) {
+            let error_message = format!(
+                "Line items add up to {} instead of the invoice amount {}",
+                total, amount
+            );
+
+            return Err(StdError::generic_err(error_message));
+        }
+    }
+
     let recurrent_status = match recurrent_time {
         Some(_time) => true,
         None => false,
@@ -81,6 +743,22 @@ pub fn new_invoice(
         critical_time: 0,
         payment_condition: "no".to_string(),
         token: token,
+        late_payment,
+        early_payment,
+        submitted_at: current_block_time,
+        discount: Uint128::zero(),
+        milestones: invoice_milestones,
+        review_period_days,
+        arbiter,
+        cancellation_policy,
+        final_payout_time: None,
+        expires_at,
+        payout_address,
+        payout_split: vec![],
+        funding_tokens: accepted_tokens,
+        pricing: None,
+        organization,
+        withholding: None,
     };
 
     InvoiceStore::save(deps.storage, &receiver, next_invoice_id, &invoice)?;
@@ -91,117 +769,302 @@ pub fn new_invoice(
         contract_process: "not started".to_string(),
         invoice: invoice,
         contract_accepted: false,
+        funded_periods: 0,
+        accrued_penalty: Uint128::zero(),
+        penalty_accrued_until: 0,
+        dispute: None,
+        pending_amendment: None,
+        amendments: vec![],
     };
 
     ContractStore::save(deps.storage, &payer_address, next_invoice_id, &contract)?;
 
-    deps.api.debug("invoice created successfully");
-    Ok(Response::default())
-}
+    if let Some(expires_at) = expires_at {
+        ExpiryStore::save(deps.storage, next_invoice_id, expires_at)?;
+    }
 
-pub fn accept_invoice(deps: DepsMut, env: Env, info: MessageInfo, id: u64) -> StdResult<Response> {
-    // get payer address from function param
-    let payer = info.sender;
+    if let Some(id) = organization {
+        OrganizationStore::add_invoice(deps.storage, id, next_invoice_id)?;
+    }
 
-    // get the contract of specific id related to invoice
-    let mut contract = ContractStore::load_contract(deps.storage, &payer, id);
+    if let Some(memo) = memo {
+        MemoStore::save(deps.storage, next_invoice_id, &memo)?;
+    }
 
-    // get invoice of specific id in related to contract
-    let mut invoice = &mut contract.invoice;
+    if !line_items.is_empty() {
+        LineItemStore::save(deps.storage, next_invoice_id, &line_items)?;
+    }
 
-    // get the receiver address
-    let receiver = deps.api.addr_validate(invoice.receiver.as_str())?;
+    PartiesStore::save(
+        deps.storage,
+        next_invoice_id,
+        &InvoiceParties {
+            payee: receiver.to_string(),
+            payer: payer_address.to_string(),
+        },
+    )?;
+
+    push_event(
+        deps.storage,
+        &env,
+        next_invoice_id,
+        &InvoiceEvent::new(
+            InvoiceEventKind::Submitted,
+            &receiver,
+            contract.invoice.amount,
+            Uint128::zero(),
+            &contract.invoice.token,
+            &env,
+        ),
+    )?;
 
-    let payer_address = deps.api.addr_validate(invoice.payer.as_str())?;
+    deps.api.debug("invoice created successfully");
+    Ok(Response::new()
+        .add_attribute_plaintext("action", "submit_invoice")
+        .add_attribute_plaintext("invoice_id", next_invoice_id.to_string())
+        .add_attribute("amount", contract.invoice.amount.to_string())
+        .add_attribute("token", token_label(&contract.invoice.token))
+        .add_attribute("payer", payer_address.to_string()))
+}
 
-    // verify that the payer in invoice
-    if payer_address != payer {
+pub fn set_funding_rate(
+    deps: DepsMut,
+    info: MessageInfo,
+    id: u64,
+    token: Token,
+    rate: Decimal,
+) -> StdResult<Response> {
+    // get the signer which is the payer
+    let payer = info.sender;
+
+    let parties = PartiesStore::load_for(deps.storage, id, &payer)?;
+
+    if parties.payer != payer.as_str() {
         return Err(StdError::generic_err(
-            "You are not the payer of this Invoice",
+            "You are not the payer of this invoice",
         ));
     }
 
-    if contract.contract_accepted == true {
+    let receiver = deps.api.addr_validate(parties.payee.as_str())?;
+
+    // get the contract of specific id related to invoice
+    let mut contract = ContractStore::load_contract(deps.storage, &payer, id);
+
+    if contract.contract_accepted {
         return Err(StdError::generic_err("Invoice have already been accepted"));
     }
 
-    let mut amount = Uint128::zero();
+    if rate.is_zero() {
+        return Err(StdError::generic_err("Funding rate must be positive"));
+    }
 
-    let mut admin_withraw_amount = Uint128::zero();
+    let funding_token = match contract
+        .invoice
+        .funding_tokens
+        .iter_mut()
+        .find(|funding| funding.token == token)
+    {
+        Some(funding_token) => funding_token,
+        None => {
+            return Err(StdError::generic_err(
+                "Token is not accepted for this invoice",
+            ))
+        }
+    };
 
-    // get admin wallet address
-    let admin_wallet = AdminStore::get_admin_wallet( deps.storage);
+    // a new rate has to be agreed by the payee again
+    funding_token.rate = Some(rate);
+    funding_token.agreed = false;
 
-    // validate admin wallet addres
-    let admin_wallet_validate = deps.api.addr_validate(admin_wallet.as_str())?;
+    // save the update
+    InvoiceStore::save(deps.storage, &receiver, id, &contract.invoice)?;
+    ContractStore::save(deps.storage, &payer, id, &contract)?;
 
-    let denom = "uscrt".to_string();
+    deps.api.debug("funding rate set successfully");
+    Ok(Response::new()
+        .add_attribute_plaintext("action", "set_funding_rate")
+        .add_attribute_plaintext("invoice_id", id.to_string())
+        .add_attribute("token", token_label(&token))
+        .add_attribute("rate", rate.to_string()))
+}
+
+pub fn agree_funding_rate(
+    deps: DepsMut,
+    info: MessageInfo,
+    id: u64,
+    token: Token,
+    rate: Decimal,
+) -> StdResult<Response> {
+    // get the signer which is the receiver of payment
+    let receiver = info.sender;
 
-    if invoice.recurrent == Some(true) {
-        let expected_amount = invoice.amount * Uint128::new(invoice.recurrent_times.into());
+    let parties = PartiesStore::load_for(deps.storage, id, &receiver)?;
 
-        let total_admin_charges = invoice.admin_charges * Uint128::new(invoice.recurrent_times.into());
+    if parties.payee != receiver.as_str() {
+        return Err(StdError::generic_err(
+            "You are not the payee of this invoice",
+        ));
+    }
 
-        let total_expected_amount = expected_amount + total_admin_charges;
+    let payer = deps.api.addr_validate(parties.payer.as_str())?;
 
-        admin_withraw_amount = total_admin_charges;
+    // get the contract of specific id related to invoice
+    let mut contract = ContractStore::load_contract(deps.storage, &payer, id);
 
-        for coin in &info.funds {
-            amount += coin.amount
-        }
+    if contract.contract_accepted {
+        return Err(StdError::generic_err("Invoice have already been accepted"));
+    }
 
-        if amount < total_expected_amount {
-            let error_message = format!(
-                "Amount {} is insufficient for recurrent payment in Invoice. Expected amount: {}",
-                amount, total_expected_amount
-            );
-        
+    // the payee agrees to the rate they have seen, not to a later one
+    let funding_token = match contract
+        .invoice
+        .funding_tokens
+        .iter_mut()
+        .find(|funding| funding.token == token && funding.rate == Some(rate))
+    {
+        Some(funding_token) => funding_token,
+        None => {
             return Err(StdError::generic_err(
-                error_message
-            ));
+                "Rate does not match the rate set by the payer",
+            ))
         }
+    };
+    funding_token.agreed = true;
 
-    } else {
-        let total_admin_charges_single = invoice.admin_charges;
+    // save the update
+    InvoiceStore::save(deps.storage, &receiver, id, &contract.invoice)?;
+    ContractStore::save(deps.storage, &payer, id, &contract)?;
+
+    deps.api.debug("funding rate agreed successfully");
+    Ok(Response::new()
+        .add_attribute_plaintext("action", "agree_funding_rate")
+        .add_attribute_plaintext("invoice_id", id.to_string())
+        .add_attribute("token", token_label(&token))
+        .add_attribute("rate", rate.to_string()))
+}
+
+pub fn accept_invoice(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+) -> StdResult<Response> {
+    // an admin of the organization billed takes the invoice over as its payer
+    claim_for_organization(&mut deps, &info.sender, id)?;
+
+    // get payer address, the signer or the payer they operate for
+    let payer = acting_payer(&deps, &env, &info.sender, id, OperatorPermission::AcceptInvoice)?;
+
+    // get the contract of specific id related to invoice
+    let mut contract = ContractStore::load_contract(deps.storage, &payer, id);
+
+    // get invoice of specific id in related to contract
+    let mut invoice = &mut contract.invoice;
+
+    // get the receiver address
+    let receiver = deps.api.addr_validate(invoice.receiver.as_str())?;
+
+    let payer_address = deps.api.addr_validate(invoice.payer.as_str())?;
+
+    // verify that the payer in invoice
+    if payer_address != payer {
+        return Err(StdError::generic_err(
+            "You are not the payer of this Invoice",
+        ));
+    }
+
+    if contract.contract_accepted == true {
+        return Err(StdError::generic_err("Invoice have already been accepted"));
+    }
+
+    if invoice.is_expired(env.block.time.seconds()) {
+        return Err(StdError::generic_err("Invoice have expired"));
+    }
+
+    // the payee has to agree to the tax withheld from their payments
+    if matches!(&invoice.withholding, Some(withholding) if !withholding.agreed) {
+        return Err(StdError::generic_err(
+            "Withholding has not been agreed by the payee",
+        ));
+    }
+
+    // get admin wallet address
+    let admin_wallet = AdminStore::get_admin_wallet( deps.storage);
+
+    // validate admin wallet addres
+    let admin_wallet_validate = deps.api.addr_validate(admin_wallet.as_str())?;
+
+    // funds attached in another accepted token reprice the invoice at the agreed rate
+    if let Some(coin) = info.funds.first() {
+        if coin.denom != invoice.denom()? {
+            let token = Token::Native(coin.denom.clone());
 
-        let total_expected_amount_single = invoice.amount + total_admin_charges_single;
+            match invoice.funding_rate(&token) {
+                Some(rate) => invoice.convert(token, rate),
+                None => {
+                    let error_message =
+                        format!("Token {} is not accepted for this invoice", coin.denom);
 
-        admin_withraw_amount = total_admin_charges_single;
+                    return Err(StdError::generic_err(error_message));
+                }
+            }
+        }
+    }
 
-        for coin in &info.funds {
-            amount += coin.amount
+    let denom = invoice.denom()?;
+    let amount = match payer == info.sender {
+        true => deposit_of(&info.funds, &denom)?,
+        // an operator funds the first payment out of the payer treasury
+        false => {
+            let needed = invoice.amount + invoice.admin_charges;
+            draw_for_operator(deps.storage, &info.funds, &payer, &denom, needed)?
         }
+    };
 
-        if amount < total_expected_amount_single {
-            let error_message = format!(
-                "Amount {} is insufficient for recurrent payment in Invoice. Expected amount: {}",
-                amount, total_expected_amount_single
-            );
-          
-            return Err(StdError::generic_err(
-                error_message
-            ));
+    let current_block_time = env.block.time.seconds();
+
+    // paying every payment before the discount deadline earns the early payment discount
+    if let Some(discounted_amount) = invoice.discounted_amount(current_block_time) {
+        let discounted = Invoice {
+            amount: discounted_amount,
+            ..invoice.clone()
+        };
+        let count = invoice.installment_count();
+
+        if periods_paid_by(&discounted, amount, count).0 == count {
+            invoice.discount = invoice.amount - discounted_amount;
+            invoice.amount = discounted_amount;
         }
     }
 
-    if amount.is_zero() {
-        return Err(StdError::generic_err("Insufficient token attach"));
+    // the deposit must cover the invoice at least once, more payments can be topped up later
+    let (funded_periods, admin_withraw_amount) =
+        periods_paid_by(invoice, amount, invoice.installment_count());
+
+    if funded_periods == 0 {
+        // a single payment can be funded at the discounted amount
+        let expected = match invoice.installment_count() {
+            1 => invoice
+                .discounted_amount(current_block_time)
+                .unwrap_or(invoice.amount),
+            _ => invoice.amount,
+        };
+        let error_message = format!(
+            "Amount {} is insufficient for payment in Invoice. Expected at least: {}",
+            amount,
+            expected + invoice.admin_charges
+        );
+
+        return Err(StdError::generic_err(error_message));
     }
 
-    let remaining_time_of_payment = match invoice.recurrent {
-        Some(_remaining_time) => invoice.recurrent_times,
-        None => 1,
-    };
+    let remaining_time_of_payment = invoice.installment_count();
 
     // transfer admin money to his wallet
-     CosmosMsg::<Empty>::Bank(BankMsg::Send {
-        to_address: admin_wallet_validate.to_string(),
-        amount: coins(admin_withraw_amount.into(), denom),
-    });
+    let admin_fee_msg = bank_send(&admin_wallet_validate, admin_withraw_amount.u128(), &denom);
 
     let account_balance = amount - admin_withraw_amount;
 
-    let current_block_time = env.block.time.seconds();
     let day_in_timestamp = invoice.days * 86400;
     let paid_time = current_block_time + day_in_timestamp;
     let critical_time = paid_time / 2;
@@ -213,25 +1076,211 @@ pub fn accept_invoice(deps: DepsMut, env: Env, info: MessageInfo, id: u64) -> St
     invoice.status = "accepted".to_string();
     invoice.remaining_time_of_payment = remaining_time_of_payment;
 
+    push_event(
+        deps.storage,
+        &env,
+        id,
+        &InvoiceEvent::new(
+            InvoiceEventKind::Accepted,
+            &payer,
+            Uint128::zero(),
+            Uint128::zero(),
+            &invoice.token,
+            &env,
+        ),
+    )?;
+    push_event(
+        deps.storage,
+        &env,
+        id,
+        &InvoiceEvent::new(
+            InvoiceEventKind::Deposited,
+            &payer,
+            account_balance,
+            admin_withraw_amount,
+            &invoice.token,
+            &env,
+        ),
+    )?;
+
     contract.account_balance = account_balance.into();
+    contract.funded_periods = funded_periods;
     contract.contract_accepted = true;
     contract.contract_process = "started".to_string();
 
     // save the update
     InvoiceStore::save(deps.storage, &receiver, id, &invoice)?;
     ContractStore::save(deps.storage, &payer, id, &contract)?;
+    ExpiryStore::remove(deps.storage, id)?;
 
     deps.api.debug("invoice accepted successfully");
-    Ok(Response::default())
+    Ok(Response::new()
+        .add_messages(admin_fee_msg)
+        .add_attribute_plaintext("action", "accept_invoice")
+        .add_attribute_plaintext("invoice_id", id.to_string())
+        .add_attribute("amount", account_balance.to_string())
+        .add_attribute("fee", admin_withraw_amount.to_string())
+        .add_attribute("token", token_label(&contract.invoice.token)))
+}
+
+pub fn top_up(deps: DepsMut, env: Env, info: MessageInfo, id: u64) -> StdResult<Response> {
+    // get the signer which is the payer, or the payer they operate for
+    let payer = acting_payer(&deps, &env, &info.sender, id, OperatorPermission::TopUp)?;
+
+    // get the contract of specific id related to invoice
+    let mut contract = ContractStore::load_contract(deps.storage, &payer, id);
+
+    let payer_address = deps.api.addr_validate(contract.invoice.payer.as_str())?;
+
+    // verify the signer in invoice
+    if payer_address != payer {
+        return Err(StdError::generic_err(
+            "You are not the payer for this invoice",
+        ));
+    }
+
+    // check if the payer has accepted the contract
+    if !contract.contract_accepted {
+        return Err(StdError::generic_err("You have not accepted this invoice"));
+    }
+
+    // only running contracts can be topped up
+    if contract.contract_process != "started" {
+        return Err(StdError::generic_err("Invoice is no longer running"));
+    }
+
+    let unfunded = contract.invoice.installment_count() - contract.funded_periods;
+    if unfunded == 0 {
+        return Err(StdError::generic_err(
+            "All payments of this invoice have been funded",
+        ));
+    }
+
+    // late penalty is paid to the payee out of the deposit first
+    let treasury = TreasuryStore::covering(deps.storage, &payer, &contract.invoice);
+    contract.accrue_penalty(env.block.time.seconds(), treasury);
+    let owed_penalty = contract.accrued_penalty;
+
+    let denom = contract.invoice.denom()?;
+    let amount = match payer == info.sender {
+        true => deposit_of(&info.funds, &denom)?,
+        // an operator funds the next payment and the penalty out of the payer treasury
+        false => {
+            let needed =
+                owed_penalty + contract.invoice.amount + contract.invoice.admin_charges;
+            draw_for_operator(deps.storage, &info.funds, &payer, &denom, needed)?
+        }
+    };
+    let penalty = take_penalty(&mut contract.accrued_penalty, amount);
+
+    let (funded_periods, admin_charges) =
+        periods_paid_by(&contract.invoice, amount - penalty, unfunded);
+
+    if funded_periods == 0 {
+        let error_message = format!(
+            "Amount {} is insufficient to fund a payment. Expected at least: {}",
+            amount,
+            owed_penalty + contract.invoice.amount + contract.invoice.admin_charges
+        );
+
+        return Err(StdError::generic_err(error_message));
+    }
+
+    let payout = Payout::of(deps.api, &contract.invoice)?;
+
+    // get admin wallet address
+    let admin_wallet = AdminStore::get_admin_wallet(deps.storage);
+
+    // validate admin wallet addres
+    let admin_wallet_validate = deps.api.addr_validate(admin_wallet.as_str())?;
+
+    // transfer admin money to his wallet
+    let admin_fee_msg = bank_send(&admin_wallet_validate, admin_charges.u128(), &denom);
+
+    // transfer the late penalty to the payee
+    let penalty_msg = payout.messages(penalty.u128(), &denom);
+
+    let deposit = amount - penalty - admin_charges;
+
+    if !penalty.is_zero() {
+        push_event(
+            deps.storage,
+            &env,
+            id,
+            &InvoiceEvent::new(
+                InvoiceEventKind::PenaltyPaid,
+                &payer,
+                penalty,
+                Uint128::zero(),
+                &contract.invoice.token,
+                &env,
+            ),
+        )?;
+    }
+
+    contract.account_balance += deposit.u128();
+    contract.funded_periods += funded_periods;
+
+    push_event(
+        deps.storage,
+        &env,
+        id,
+        &InvoiceEvent::new(
+            InvoiceEventKind::Deposited,
+            &payer,
+            deposit,
+            admin_charges,
+            &contract.invoice.token,
+            &env,
+        ),
+    )?;
+
+    // save the update
+    ContractStore::save(deps.storage, &payer, id, &contract)?;
+
+    deps.api.debug("invoice topped up successfully");
+    Ok(Response::new()
+        .add_messages(admin_fee_msg)
+        .add_messages(penalty_msg)
+        .add_attribute_plaintext("action", "top_up")
+        .add_attribute_plaintext("invoice_id", id.to_string())
+        .add_attribute("amount", deposit.to_string())
+        .add_attribute("fee", admin_charges.to_string())
+        .add_attribute("penalty", penalty.to_string())
+        .add_attribute("token", token_label(&contract.invoice.token)))
 }
 
 pub fn stop_contract(deps: DepsMut, env: Env, info: MessageInfo, id: u64) -> StdResult<Response> {
-    // get the signer which is the payer
-    let payer = info.sender;
+    // get the signer which is the payer, or the payer they operate for
+    let payer = acting_payer(&deps, &env, &info.sender, id, OperatorPermission::CancelPayment)?;
 
     // get the contract of specific id related to invoice
     let mut contract = ContractStore::load_contract(deps.storage, &payer, id);
 
+    let payout = Payout::of(deps.api, &contract.invoice)?;
+
+    let current_block_time = env.block.time.seconds();
+
+    // late penalty owed so far is settled before the payer is refunded
+    let treasury = TreasuryStore::covering(deps.storage, &payer, &contract.invoice);
+    contract.accrue_penalty(current_block_time, treasury);
+
+    // what the payee keeps: the agreed policy, or else half of the current
+    // payment before the critical time
+    let payee_claim = match &contract.invoice.cancellation_policy {
+        Some(policy) => Some(contract.cancellation_share(policy, current_block_time))
+            .filter(|share| !share.is_zero()),
+        None if contract.invoice.critical_time > current_block_time
+            && !contract.invoice.is_milestone_based() =>
+        {
+            Some(
+                (contract.invoice.amount / Uint128::new(2))
+                    .min(Uint128::from(contract.account_balance)),
+            )
+        }
+        None => None,
+    };
+
     // get invoice of specific id in related to contract
     let mut invoice = &mut contract.invoice;
 
@@ -264,23 +1313,68 @@ pub fn stop_contract(deps: DepsMut, env: Env, info: MessageInfo, id: u64) -> Std
         return Err(StdError::generic_err("Invoice have already been canceled"));
     }
 
-    let current_block_time = env.block.time.seconds();
+    if contract.dispute.is_some() {
+        return Err(StdError::generic_err("Invoice is under dispute"));
+    }
+
+    let denom = invoice.denom()?;
 
-    let denom = "uscrt".to_string();
+    // submitted work has to be approved before the invoice can be canceled
+    if invoice
+        .milestones
+        .iter()
+        .any(|milestone| milestone.status == MilestoneStatus::Submitted)
+    {
+        return Err(StdError::generic_err(
+            "A milestone is waiting for approval",
+        ));
+    }
 
-    if invoice.critical_time > current_block_time {
-        // set the amount to half of current payment
-        let amount_to_pay = invoice.amount / Uint128::new(2);
+    let mut messages = vec![];
+    let refund: u128;
+    let payee_share: Uint128;
+    let penalty: Uint128;
 
+    if let Some(amount_to_pay) = payee_claim {
         // get the remaining balance
         let remaining_balance: u128 =
             contract.account_balance - <Uint128 as Into<u128>>::into(amount_to_pay);
 
+        // payee receive the late penalty out of it
+        penalty = take_penalty(&mut contract.accrued_penalty, remaining_balance.into());
+        let remaining_balance = remaining_balance - penalty.u128();
+
         // payer should receive their remaining balance
-        CosmosMsg::<Empty>::Bank(BankMsg::Send {
-            to_address: payer.to_string(),
-            amount: coins(remaining_balance, denom),
-        });
+        messages.extend(bank_send(&payer, remaining_balance, &denom));
+        refund = remaining_balance;
+        payee_share = amount_to_pay;
+
+        push_event(
+            deps.storage,
+            &env,
+            id,
+            &InvoiceEvent::new(
+                InvoiceEventKind::Cancelled,
+                &payer,
+                amount_to_pay,
+                Uint128::zero(),
+                &invoice.token,
+                &env,
+            ),
+        )?;
+        push_event(
+            deps.storage,
+            &env,
+            id,
+            &InvoiceEvent::new(
+                InvoiceEventKind::Refunded,
+                &payer,
+                remaining_balance.into(),
+                Uint128::zero(),
+                &invoice.token,
+                &env,
+            ),
+        )?;
 
         invoice.payment_condition = "half".to_string();
         invoice.amount = amount_to_pay;
@@ -294,11 +1388,41 @@ pub fn stop_contract(deps: DepsMut, env: Env, info: MessageInfo, id: u64) -> Std
         InvoiceStore::save(deps.storage, &receiver, id, &invoice)?;
         ContractStore::save(deps.storage, &payer, id, &contract)?;
     } else {
+        // payee receive the late penalty out of the balance
+        penalty = take_penalty(&mut contract.accrued_penalty, contract.account_balance.into());
+        contract.account_balance -= penalty.u128();
+
         // payer should receive all pending their money back
-        CosmosMsg::<Empty>::Bank(BankMsg::Send {
-            to_address: payer.to_string(),
-            amount: coins(contract.account_balance, denom),
-        });
+        messages.extend(bank_send(&payer, contract.account_balance, &denom));
+        refund = contract.account_balance;
+        payee_share = Uint128::zero();
+
+        push_event(
+            deps.storage,
+            &env,
+            id,
+            &InvoiceEvent::new(
+                InvoiceEventKind::Cancelled,
+                &payer,
+                Uint128::zero(),
+                Uint128::zero(),
+                &invoice.token,
+                &env,
+            ),
+        )?;
+        push_event(
+            deps.storage,
+            &env,
+            id,
+            &InvoiceEvent::new(
+                InvoiceEventKind::Refunded,
+                &payer,
+                contract.account_balance.into(),
+                Uint128::zero(),
+                &invoice.token,
+                &env,
+            ),
+        )?;
 
         invoice.payment_condition = "no".to_string();
         invoice.amount = Uint128::new(0);
@@ -313,167 +1437,1373 @@ pub fn stop_contract(deps: DepsMut, env: Env, info: MessageInfo, id: u64) -> Std
         ContractStore::save(deps.storage, &payer, id, &contract)?;
     }
 
+    if !penalty.is_zero() {
+        push_event(
+            deps.storage,
+            &env,
+            id,
+            &InvoiceEvent::new(
+                InvoiceEventKind::PenaltyPaid,
+                &payer,
+                penalty,
+                Uint128::zero(),
+                &contract.invoice.token,
+                &env,
+            ),
+        )?;
+    }
+
+    // payee receive the late penalty
+    messages.extend(payout.messages(penalty.u128(), &denom));
+
     deps.api.debug("invoice canceled successfully");
-    Ok(Response::default())
+    Ok(Response::new()
+        .add_messages(messages)
+        .add_attribute_plaintext("action", "cancel_payment")
+        .add_attribute_plaintext("invoice_id", id.to_string())
+        .add_attribute("amount", refund.to_string())
+        .add_attribute("payee_share", payee_share.to_string())
+        .add_attribute("penalty", penalty.to_string())
+        .add_attribute("token", token_label(&contract.invoice.token)))
 }
 
-pub fn withdraw_payment(
+/// Ends a recurring contract after the payments already due and the notice
+/// periods, refunding the escrow of the payments after them.
+pub fn terminate_with_notice(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
     id: u64,
 ) -> StdResult<Response> {
-    // get the signer which is the receiver of payment
-    let receiver = info.sender;
-
-    // get invoice of specific id in related to contract
-    let mut invoice = InvoiceStore::load_invoice(deps.storage, &receiver, id);
-
-    // receiver address in the invoice
-    let receiver_address = deps.api.addr_validate(invoice.receiver.as_str())?;
+    // get the signer which is the payer
+    let payer = info.sender;
 
-    // verify payer address in the invoice
-    let payer = deps.api.addr_validate(invoice.payer.as_str())?;
+    let parties = PartiesStore::load_for(deps.storage, id, &payer)?;
 
-    // check that the signer is one that submitted the invoice
-    if receiver_address != receiver {
+    if parties.payer != payer.as_str() {
         return Err(StdError::generic_err(
-            "You are not the payee of this invoice",
+            "You are not the payer for this invoice",
         ));
     }
 
+    let receiver = deps.api.addr_validate(parties.payee.as_str())?;
+
     // get the contract of specific id related to invoice
-    let contract = ContractStore::load_contract(deps.storage, &payer, id);
+    let mut contract = ContractStore::load_contract(deps.storage, &payer, id);
 
-    // check if the contract has been accepted
-    if contract.contract_accepted != true {
-        return Err(StdError::generic_err("Invoice have not been accepted"));
-    }
+    let payout = Payout::of(deps.api, &contract.invoice)?;
 
-    let current_block_time = env.block.time.seconds();
+    if contract.contract_process != "started" || contract.invoice.remaining_time_of_payment == 0 {
+        return Err(StdError::generic_err("Invoice is not running"));
+    }
 
-    if current_block_time < invoice.payment_time {
+    if contract.invoice.recurrent != Some(true) {
         return Err(StdError::generic_err(
-            "Payment period have not been reached for this",
+            "Only recurring contracts can be terminated with notice",
         ));
     }
 
-    if invoice.remaining_time_of_payment == 0 {
+    if contract.invoice.final_payout_time.is_some() {
         return Err(StdError::generic_err(
-            "All payment have been made for this invoice",
+            "Contract have already been terminated",
         ));
     }
 
-    if invoice.payment_condition == "no".to_string() {
-        return Err(StdError::generic_err("Invoice have been canceled"));
-    }
-
-    // check if the payer has money in is account
-    if contract.account_balance < 1 {
-        return Err(StdError::generic_err(
-            "No payment reserved for this Invoice",
-        ));
+    if contract.dispute.is_some() {
+        return Err(StdError::generic_err("Invoice is under dispute"));
     }
 
-    let denom = "uscrt".to_string();
+    let current_block_time = env.block.time.seconds();
 
-    // get admin wallet address
-    let admin_wallet = AdminStore::get_admin_wallet( deps.storage);
+    // late penalty owed so far is settled before the payer is refunded
+    let treasury = TreasuryStore::covering(deps.storage, &payer, &contract.invoice);
+    contract.accrue_penalty(current_block_time, treasury);
 
-    // validate admin wallet addres
-    let admin_wallet_validate = deps.api.addr_validate(admin_wallet.as_str())?;
+    let invoice = &mut contract.invoice;
+    let next = invoice.next_installment();
 
-    if invoice.payment_condition == "half".to_string() {
-        invoice.status = "done".to_string();
-        invoice.remaining_time_of_payment = 0;
+    // payments already due stay payable, followed by the notice periods
+    let mut due = 0;
+    while next + due < invoice.installment_count()
+        && invoice.installment_due_time(next + due) <= current_block_time
+    {
+        due += 1;
+    }
+    let kept = (due + invoice.notice_periods()).min(invoice.remaining_time_of_payment);
+
+    invoice.recurrent_times = next + kept;
+    invoice.remaining_time_of_payment = kept;
+    let final_payout_time = invoice.installment_due_time(next + kept - 1);
+    invoice.final_payout_time = Some(final_payout_time);
+
+    // a pending amendment would undo the termination
+    contract.pending_amendment = None;
+
+    // escrow beyond the kept payments goes back to the payer
+    let needed = invoice.amount.u128() * u128::from(kept);
+    let surplus = contract.account_balance.saturating_sub(needed);
+    contract.account_balance -= surplus;
+    contract.funded_periods = contract.funded_periods.min(next + kept);
+
+    // payee receive the late penalty out of it
+    let penalty = take_penalty(&mut contract.accrued_penalty, surplus.into());
+    let refund = surplus - penalty.u128();
+
+    let token = contract.invoice.token.clone();
+
+    push_event(
+        deps.storage,
+        &env,
+        id,
+        &InvoiceEvent::new(
+            InvoiceEventKind::Cancelled,
+            &payer,
+            Uint128::from(needed),
+            Uint128::zero(),
+            &token,
+            &env,
+        ),
+    )?;
+    if refund > 0 {
+        push_event(
+            deps.storage,
+            &env,
+            id,
+            &InvoiceEvent::new(
+                InvoiceEventKind::Refunded,
+                &payer,
+                refund.into(),
+                Uint128::zero(),
+                &token,
+                &env,
+            ),
+        )?;
+    }
+    if !penalty.is_zero() {
+        push_event(
+            deps.storage,
+            &env,
+            id,
+            &InvoiceEvent::new(
+                InvoiceEventKind::PenaltyPaid,
+                &payer,
+                penalty,
+                Uint128::zero(),
+                &token,
+                &env,
+            ),
+        )?;
+    }
 
-        let changes = invoice.customer_charges;
+    // save the update
+    InvoiceStore::save(deps.storage, &receiver, id, &contract.invoice)?;
+    ContractStore::save(deps.storage, &payer, id, &contract)?;
 
-        let payee_payment = invoice.amount - changes;
+    let denom = contract.invoice.denom()?;
+
+    deps.api.debug("contract terminated successfully");
+    Ok(Response::new()
+        .add_messages(bank_send(&payer, refund, &denom))
+        .add_messages(payout.messages(penalty.u128(), &denom))
+        .add_attribute_plaintext("action", "terminate_with_notice")
+        .add_attribute_plaintext("invoice_id", id.to_string())
+        .add_attribute("amount", refund.to_string())
+        .add_attribute("remaining_payments", kept.to_string())
+        .add_attribute("final_payout_time", final_payout_time.to_string())
+        .add_attribute("penalty", penalty.to_string())
+        .add_attribute("token", token_label(&token)))
+}
 
-        // save invoice changes
-        InvoiceStore::save(deps.storage, &receiver, id, &invoice)?;
+/// Ends a contract on the payee side: the payments already due are paid out as
+/// far as the balance covers them and the rest of the escrow is refunded.
+pub fn resign(mut deps: DepsMut, env: Env, info: MessageInfo, id: u64) -> StdResult<Response> {
+    // get the signer which is the receiver of payment
+    let receiver = info.sender;
 
-        let contract_store = Contract {
-            invoice_id: id,
-            account_balance: 0,
-            contract_process: contract.contract_process,
-            invoice,
-            contract_accepted: contract.contract_accepted,
-        };
+    let parties = PartiesStore::load_for(deps.storage, id, &receiver)?;
 
-        // employee receive their payment
-        CosmosMsg::<Empty>::Bank(BankMsg::Send {
-            to_address: receiver.to_string(),
-            amount: coins(payee_payment.into(), denom.clone()),
-        });
+    if parties.payee != receiver.as_str() {
+        return Err(StdError::generic_err(
+            "You are not the payee of this invoice",
+        ));
+    }
 
-        // admin receive his changes
-        CosmosMsg::<Empty>::Bank(BankMsg::Send {
-            to_address: admin_wallet_validate.to_string(),
-            amount: coins(changes.into(), denom.clone()),
-        });
+    let payer = deps.api.addr_validate(parties.payer.as_str())?;
 
-        // save contract changes
-        ContractStore::save(deps.storage, &payer, id, &contract_store)?;
-    } else {
-        //get the remaining time
-        let remaining_time_of_payment = match invoice.recurrent {
-            Some(_remaining_time) => invoice.remaining_time_of_payment - 1,
-            None => 0,
-        };
+    // get the contract of specific id related to invoice
+    let mut contract = ContractStore::load_contract(deps.storage, &payer, id);
 
-        let account_balance =
-            contract.account_balance - <Uint128 as Into<u128>>::into(invoice.amount);
+    let payout = Payout::of(deps.api, &contract.invoice)?;
 
-        invoice.remaining_time_of_payment = remaining_time_of_payment;
+    if contract.contract_process != "started" || contract.invoice.remaining_time_of_payment == 0 {
+        return Err(StdError::generic_err("Invoice is not running"));
+    }
 
-        let changes = invoice.customer_charges;
+    if contract.dispute.is_some() {
+        return Err(StdError::generic_err("Invoice is under dispute"));
+    }
 
-        let payee_payment = invoice.amount - changes;
+    let current_block_time = env.block.time.seconds();
 
-        // save invoice changes
-        InvoiceStore::save(deps.storage, &receiver, id, &invoice)?;
+    // late penalty owed so far is settled before the payer is refunded
+    let treasury = TreasuryStore::covering(deps.storage, &payer, &contract.invoice);
+    contract.accrue_penalty(current_block_time, treasury);
+
+    let invoice = &contract.invoice;
+
+    // payments already due, as far as the balance covers them
+    let mut earned = 0u64;
+    if !invoice.is_milestone_based() {
+        let next = invoice.next_installment();
+        while next + earned < invoice.installment_count()
+            && invoice.installment_due_time(next + earned) <= current_block_time
+            && invoice.amount.u128() * u128::from(earned + 1) <= contract.account_balance
+        {
+            earned += 1;
+        }
+    }
 
-        let contract_store = Contract {
-            invoice_id: id,
-            account_balance: account_balance,
-            contract_process: contract.contract_process,
-            invoice: invoice,
-            contract_accepted: contract.contract_accepted,
-        };
+    let gross_amount = invoice.amount * Uint128::from(earned);
+    let fee = (invoice.customer_charges * Uint128::from(earned)).min(gross_amount);
+
+    let remaining_balance = contract.account_balance - gross_amount.u128();
+
+    // payee receive the late penalty out of it
+    let penalty = take_penalty(&mut contract.accrued_penalty, remaining_balance.into());
+    let refund = remaining_balance - penalty.u128();
+
+    let token = invoice.token.clone();
+
+    // the payee is paid the payments already due
+    let messages = match earned > 0 {
+        true => pay_out(
+            &mut deps,
+            &env,
+            &contract,
+            gross_amount,
+            fee,
+            InvoiceEventKind::Withdrawn,
+            &receiver,
+        )?,
+        false => vec![],
+    };
+    push_event(
+        deps.storage,
+        &env,
+        id,
+        &InvoiceEvent::new(
+            InvoiceEventKind::Cancelled,
+            &receiver,
+            Uint128::zero(),
+            Uint128::zero(),
+            &token,
+            &env,
+        ),
+    )?;
+    if refund > 0 {
+        push_event(
+            deps.storage,
+            &env,
+            id,
+            &InvoiceEvent::new(
+                InvoiceEventKind::Refunded,
+                &payer,
+                refund.into(),
+                Uint128::zero(),
+                &token,
+                &env,
+            ),
+        )?;
+    }
+    if !penalty.is_zero() {
+        push_event(
+            deps.storage,
+            &env,
+            id,
+            &InvoiceEvent::new(
+                InvoiceEventKind::PenaltyPaid,
+                &payer,
+                penalty,
+                Uint128::zero(),
+                &token,
+                &env,
+            ),
+        )?;
+    }
 
-        // employee receive their payment
-        CosmosMsg::<Empty>::Bank(BankMsg::Send {
-            to_address: receiver.to_string(),
-            amount: coins(payee_payment.into(), denom.clone()),
-        });
+    contract.invoice.payment_condition = "no".to_string();
+    contract.invoice.status = "stop".to_string();
+    contract.invoice.remaining_time_of_payment = 0;
 
-        // admin receive his changes
-        CosmosMsg::<Empty>::Bank(BankMsg::Send {
-            to_address: admin_wallet_validate.to_string(),
-            amount: coins(changes.into(), denom.clone()),
-        });
+    contract.contract_process = "stop".to_string();
+    contract.account_balance = 0;
+    contract.pending_amendment = None;
 
-        // save contract changes
-        ContractStore::save(deps.storage, &payer, id, &contract_store)?;
-    }
+    // save the update
+    InvoiceStore::save(deps.storage, &receiver, id, &contract.invoice)?;
+    ContractStore::save(deps.storage, &payer, id, &contract)?;
 
-    deps.api.debug("invoice accepted successfully");
-    Ok(Response::default())
+    let denom = contract.invoice.denom()?;
+
+    deps.api.debug("payee resigned successfully");
+    Ok(Response::new()
+        .add_messages(messages)
+        .add_messages(payout.messages(penalty.u128(), &denom))
+        .add_messages(bank_send(&payer, refund, &denom))
+        .add_attribute_plaintext("action", "resign")
+        .add_attribute_plaintext("invoice_id", id.to_string())
+        .add_attribute("payee_share", gross_amount.to_string())
+        .add_attribute("fee", fee.to_string())
+        .add_attribute("amount", refund.to_string())
+        .add_attribute("penalty", penalty.to_string())
+        .add_attribute("token", token_label(&token)))
 }
 
-pub fn admin_change_admin(deps: DepsMut, env: Env, info: MessageInfo, admin: String) -> StdResult<Response> {
-    // get the signer which is the payer
+pub fn propose_amendment(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+    new_amount: Uint128,
+    new_period: u64,
+    new_count: u64,
+) -> StdResult<Response> {
+    // get the signer which is the payee or the payer
     let sender = info.sender;
 
-    let admin_wallet = AdminStore::get_admin_wallet( deps.storage);
+    let parties = PartiesStore::load_for(deps.storage, id, &sender)?;
 
-    let admin_wallet_validate = deps.api.addr_validate(admin_wallet.as_str())?;
+    if !parties.is_party(&sender) {
+        return Err(StdError::generic_err("You are not a party to this invoice"));
+    }
 
-    // check the signer ias admin
-    if sender != admin_wallet_validate {
-        return Err(StdError::generic_err(
+    let payer = deps.api.addr_validate(parties.payer.as_str())?;
+
+    // get the contract of specific id related to invoice
+    let mut contract = ContractStore::load_contract(deps.storage, &payer, id);
+
+    if contract.contract_process != "started" || contract.invoice.remaining_time_of_payment == 0 {
+        return Err(StdError::generic_err("Invoice is not running"));
+    }
+
+    if contract.invoice.is_milestone_based() || contract.invoice.final_payout_time.is_some() {
+        return Err(StdError::generic_err(
+            "Only scheduled contracts can be amended",
+        ));
+    }
+
+    if new_amount.is_zero() || new_count == 0 || (new_count > 1 && new_period == 0) {
+        return Err(StdError::generic_err(
+            "Amendment must keep at least one payment of a positive amount",
+        ));
+    }
+
+    // charges are in the token the invoice is paid in, converted along with the amount
+    if new_amount < contract.invoice.customer_charges {
+        return Err(StdError::generic_err(
+            "Amendment can not drop the amount below the charge on payee",
+        ));
+    }
+
+    // a new proposal replaces the pending one
+    contract.pending_amendment = Some(Amendment {
+        proposed_by: sender.to_string(),
+        new_amount,
+        new_period,
+        new_count,
+        proposed_at: env.block.time.seconds(),
+        accepted_at: None,
+    });
+
+    push_event(
+        deps.storage,
+        &env,
+        id,
+        &InvoiceEvent::new(
+            InvoiceEventKind::AmendmentProposed,
+            &sender,
+            new_amount,
+            Uint128::zero(),
+            &contract.invoice.token,
+            &env,
+        ),
+    )?;
+
+    // save the update
+    ContractStore::save(deps.storage, &payer, id, &contract)?;
+
+    deps.api.debug("amendment proposed successfully");
+    Ok(Response::new()
+        .add_attribute_plaintext("action", "propose_amendment")
+        .add_attribute_plaintext("invoice_id", id.to_string())
+        .add_attribute("new_amount", new_amount.to_string())
+        .add_attribute("new_period", new_period.to_string())
+        .add_attribute("new_count", new_count.to_string()))
+}
+
+/// Applies the pending amendment to the payments left. The escrow keeps
+/// covering the payments it covered before at the new amount: the surplus is
+/// refunded, and the shortfall is paid by funds the payer attaches or else
+/// drawn from the payer treasury.
+pub fn accept_amendment(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+) -> StdResult<Response> {
+    // get the signer which is the counterparty of the proposal
+    let sender = info.sender;
+
+    let parties = PartiesStore::load_for(deps.storage, id, &sender)?;
+
+    if !parties.is_party(&sender) {
+        return Err(StdError::generic_err("You are not a party to this invoice"));
+    }
+
+    let receiver = deps.api.addr_validate(parties.payee.as_str())?;
+    let payer = deps.api.addr_validate(parties.payer.as_str())?;
+
+    // get the contract of specific id related to invoice
+    let mut contract = ContractStore::load_contract(deps.storage, &payer, id);
+
+    let mut amendment = match contract.pending_amendment.take() {
+        Some(amendment) => amendment,
+        None => return Err(StdError::generic_err("No amendment to accept")),
+    };
+
+    if amendment.proposed_by == sender.as_str() {
+        return Err(StdError::generic_err(
+            "Amendment must be accepted by the counterparty",
+        ));
+    }
+
+    if contract.contract_process != "started" || contract.invoice.remaining_time_of_payment == 0 {
+        return Err(StdError::generic_err("Invoice is not running"));
+    }
+
+    if contract.dispute.is_some() {
+        return Err(StdError::generic_err("Invoice is under dispute"));
+    }
+
+    // the contract may have been terminated since the proposal
+    if contract.invoice.is_milestone_based() || contract.invoice.final_payout_time.is_some() {
+        return Err(StdError::generic_err(
+            "Only scheduled contracts can be amended",
+        ));
+    }
+
+    let denom = contract.invoice.denom()?;
+    let attached = deposit_of(&info.funds, &denom)?;
+
+    if !attached.is_zero() && sender != payer {
+        return Err(StdError::generic_err("Only the payer can fund an amendment"));
+    }
+
+    let current_block_time = env.block.time.seconds();
+    let invoice = &mut contract.invoice;
+    let next = invoice.next_installment();
+
+    // the payments covered by the escrow stay covered at the new amount
+    let covered = contract
+        .funded_periods
+        .saturating_sub(next)
+        .min(amendment.new_count);
+    let required = amendment.new_amount * Uint128::from(covered);
+    let available = Uint128::from(contract.account_balance) + attached;
+
+    let mut messages = vec![];
+    let mut drawn = Uint128::zero();
+    let refund = available.saturating_sub(required);
+
+    if available < required {
+        drawn = required - available;
+
+        let treasury_balance = TreasuryStore::available(deps.storage, &payer, &denom);
+        if treasury_balance < drawn {
+            let error_message = format!(
+                "Amendment needs {} more in escrow, attach it or deposit it in the treasury",
+                drawn
+            );
+
+            return Err(StdError::generic_err(error_message));
+        }
+
+        TreasuryStore::save(deps.storage, &payer, treasury_balance - drawn)?;
+    }
+
+    // the next payment keeps its due date
+    let next_due_time = invoice.installment_due_time(next);
+    invoice.amount = amendment.new_amount;
+    invoice.days = amendment.new_period;
+    invoice.payment_time = next_due_time.saturating_sub(next * invoice.period_seconds());
+    invoice.recurrent = Some(true);
+    invoice.recurrent_times = next + amendment.new_count;
+    invoice.remaining_time_of_payment = amendment.new_count;
+
+    let token = invoice.token.clone();
+
+    contract.account_balance = required.u128();
+    contract.funded_periods = next + covered;
+
+    amendment.accepted_at = Some(current_block_time);
+    contract.amendments.push(amendment);
+
+    push_event(
+        deps.storage,
+        &env,
+        id,
+        &InvoiceEvent::new(
+            InvoiceEventKind::AmendmentAccepted,
+            &sender,
+            contract.invoice.amount,
+            Uint128::zero(),
+            &token,
+            &env,
+        ),
+    )?;
+    if !(attached + drawn).is_zero() {
+        push_event(
+            deps.storage,
+            &env,
+            id,
+            &InvoiceEvent::new(
+                InvoiceEventKind::Deposited,
+                &payer,
+                attached + drawn,
+                Uint128::zero(),
+                &token,
+                &env,
+            ),
+        )?;
+    }
+    if !refund.is_zero() {
+        push_event(
+            deps.storage,
+            &env,
+            id,
+            &InvoiceEvent::new(
+                InvoiceEventKind::Refunded,
+                &payer,
+                refund,
+                Uint128::zero(),
+                &token,
+                &env,
+            ),
+        )?;
+
+        // payer should receive the surplus of the escrow
+        messages.extend(bank_send(&payer, refund.u128(), &denom));
+    }
+
+    // save the update
+    InvoiceStore::save(deps.storage, &receiver, id, &contract.invoice)?;
+    ContractStore::save(deps.storage, &payer, id, &contract)?;
+
+    deps.api.debug("amendment accepted successfully");
+    Ok(Response::new()
+        .add_messages(messages)
+        .add_attribute_plaintext("action", "accept_amendment")
+        .add_attribute_plaintext("invoice_id", id.to_string())
+        .add_attribute("deposit", (attached + drawn).to_string())
+        .add_attribute("amount", refund.to_string())
+        .add_attribute("token", token_label(&token)))
+}
+
+pub fn withdraw_payment(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+) -> StdResult<Response> {
+    // get the signer which is the receiver of payment
+    let receiver = info.sender;
+
+    // get invoice of specific id in related to contract
+    let mut invoice = InvoiceStore::load_invoice(deps.storage, &receiver, id);
+
+    // receiver address in the invoice
+    let receiver_address = deps.api.addr_validate(invoice.receiver.as_str())?;
+
+    // verify payer address in the invoice
+    let payer = deps.api.addr_validate(invoice.payer.as_str())?;
+
+    // check that the signer is one that submitted the invoice
+    if receiver_address != receiver {
+        return Err(StdError::generic_err(
+            "You are not the payee of this invoice",
+        ));
+    }
+
+    // get the contract of specific id related to invoice
+    let mut contract = ContractStore::load_contract(deps.storage, &payer, id);
+
+    // check if the contract has been accepted
+    if contract.contract_accepted != true {
+        return Err(StdError::generic_err("Invoice have not been accepted"));
+    }
+
+    if contract.dispute.is_some() {
+        return Err(StdError::generic_err("Invoice is under dispute"));
+    }
+
+    let current_block_time = env.block.time.seconds();
+
+    // each payment of the schedule can only be withdrawn once it falls due
+    let due_time = match invoice.payment_condition == "half" {
+        true => invoice.payment_time,
+        false => invoice.installment_due_time(invoice.next_installment()),
+    };
+
+    if current_block_time < due_time {
+        return Err(StdError::generic_err(
+            "Payment period have not been reached for this",
+        ));
+    }
+
+    if invoice.remaining_time_of_payment == 0 {
+        return Err(StdError::generic_err(
+            "All payment have been made for this invoice",
+        ));
+    }
+
+    if invoice.is_milestone_based() && invoice.payment_condition != "half" {
+        return Err(StdError::generic_err(
+            "Milestone invoices are paid out when their milestones are approved",
+        ));
+    }
+
+    if invoice.payment_condition == "no".to_string() {
+        return Err(StdError::generic_err("Invoice have been canceled"));
+    }
+
+    let denom = invoice.denom()?;
+
+    // get admin wallet address
+    let admin_wallet = AdminStore::get_admin_wallet( deps.storage);
+
+    // validate admin wallet addres
+    let admin_wallet_validate = deps.api.addr_validate(admin_wallet.as_str())?;
+
+    let mut messages = vec![];
+
+    // draw what the escrow is missing for this payment from the payer treasury
+    if invoice.payment_condition != "half" {
+        let (escrow_shortfall, admin_fee) = contract.next_payment_shortfall();
+        let needed = escrow_shortfall + admin_fee;
+
+        if !needed.is_zero() {
+            let treasury_balance = TreasuryStore::available(deps.storage, &payer, &denom);
+
+            if treasury_balance < needed {
+                return Err(StdError::generic_err(
+                    "Payment has not been funded yet, waiting for the payer to top up",
+                ));
+            }
+
+            TreasuryStore::save(deps.storage, &payer, treasury_balance - needed)?;
+
+            if !contract.next_payment_funded() {
+                contract.funded_periods += 1;
+            }
+            contract.account_balance += escrow_shortfall.u128();
+
+            // admin receive the charge of the payment
+            messages.extend(bank_send(&admin_wallet_validate, admin_fee.u128(), &denom));
+
+            push_event(
+                deps.storage,
+                &env,
+                id,
+                &InvoiceEvent::new(
+                    InvoiceEventKind::Deposited,
+                    &payer,
+                    escrow_shortfall,
+                    admin_fee,
+                    &invoice.token,
+                    &env,
+                ),
+            )?;
+        }
+    }
+
+    // check if the payer has money in is account
+    if contract.account_balance < 1 {
+        return Err(StdError::generic_err(
+            "No payment reserved for this Invoice",
+        ));
+    }
+    let gross_amount = invoice.amount;
+    let fee = invoice.customer_charges;
+    let token = invoice.token.clone();
+
+    if invoice.payment_condition == "half".to_string() {
+        invoice.status = "done".to_string();
+        invoice.remaining_time_of_payment = 0;
+
+        let changes = invoice.customer_charges.min(invoice.amount);
+
+        // save invoice changes
+        InvoiceStore::save(deps.storage, &receiver, id, &invoice)?;
+
+        let contract_store = Contract {
+            invoice_id: id,
+            account_balance: 0,
+            contract_process: contract.contract_process,
+            invoice,
+            contract_accepted: contract.contract_accepted,
+            funded_periods: contract.funded_periods,
+            accrued_penalty: contract.accrued_penalty,
+            penalty_accrued_until: contract.penalty_accrued_until,
+            dispute: contract.dispute,
+            pending_amendment: contract.pending_amendment,
+            amendments: contract.amendments,
+        };
+
+        // employee receive their payment, admin receive his changes
+        messages.extend(pay_out(
+            &mut deps,
+            &env,
+            &contract_store,
+            gross_amount,
+            changes,
+            InvoiceEventKind::Withdrawn,
+            &receiver,
+        )?);
+
+        // save contract changes
+        ContractStore::save(deps.storage, &payer, id, &contract_store)?;
+    } else {
+        //get the remaining time
+        let remaining_time_of_payment = match invoice.recurrent {
+            Some(_remaining_time) => invoice.remaining_time_of_payment - 1,
+            None => 0,
+        };
+
+        let account_balance =
+            contract.account_balance - <Uint128 as Into<u128>>::into(invoice.amount);
+
+        invoice.remaining_time_of_payment = remaining_time_of_payment;
+
+        let changes = invoice.customer_charges.min(invoice.amount);
+
+        // save invoice changes
+        InvoiceStore::save(deps.storage, &receiver, id, &invoice)?;
+
+        let contract_store = Contract {
+            invoice_id: id,
+            account_balance: account_balance,
+            contract_process: contract.contract_process,
+            invoice: invoice,
+            contract_accepted: contract.contract_accepted,
+            funded_periods: contract.funded_periods,
+            accrued_penalty: contract.accrued_penalty,
+            penalty_accrued_until: contract.penalty_accrued_until,
+            dispute: contract.dispute,
+            pending_amendment: contract.pending_amendment,
+            amendments: contract.amendments,
+        };
+
+        // employee receive their payment, admin receive his changes
+        messages.extend(pay_out(
+            &mut deps,
+            &env,
+            &contract_store,
+            gross_amount,
+            changes,
+            InvoiceEventKind::Withdrawn,
+            &receiver,
+        )?);
+
+        // save contract changes
+        ContractStore::save(deps.storage, &payer, id, &contract_store)?;
+    }
+
+    deps.api.debug("invoice accepted successfully");
+    Ok(Response::new()
+        .add_messages(messages)
+        .add_attribute_plaintext("action", "withdraw_payment")
+        .add_attribute_plaintext("invoice_id", id.to_string())
+        .add_attribute("amount", gross_amount.to_string())
+        .add_attribute("fee", fee.to_string())
+        .add_attribute("token", token_label(&token)))
+}
+
+pub fn set_payout_address(
+    deps: DepsMut,
+    info: MessageInfo,
+    id: u64,
+    address: Option<String>,
+) -> StdResult<Response> {
+    // get the signer which is the receiver of payment
+    let receiver = info.sender;
+
+    let parties = PartiesStore::load_for(deps.storage, id, &receiver)?;
+
+    // only the payee can choose where they are paid
+    if parties.payee != receiver.as_str() {
+        return Err(StdError::generic_err(
+            "You are not the payee of this invoice",
+        ));
+    }
+
+    let payer = deps.api.addr_validate(parties.payer.as_str())?;
+
+    let address = match address {
+        Some(address) => Some(deps.api.addr_validate(address.as_str())?.to_string()),
+        None => None,
+    };
+
+    // get the contract of specific id related to invoice
+    let mut contract = ContractStore::load_contract(deps.storage, &payer, id);
+    contract.invoice.payout_address = address;
+
+    // save the update
+    InvoiceStore::save(deps.storage, &receiver, id, &contract.invoice)?;
+    ContractStore::save(deps.storage, &payer, id, &contract)?;
+
+    let payout = contract
+        .invoice
+        .payout_address
+        .unwrap_or(contract.invoice.receiver);
+
+    deps.api.debug("payout address updated successfully");
+    Ok(Response::new()
+        .add_attribute_plaintext("action", "set_payout_address")
+        .add_attribute_plaintext("invoice_id", id.to_string())
+        .add_attribute("payout_address", payout))
+}
+
+pub fn set_payout_split(
+    deps: DepsMut,
+    info: MessageInfo,
+    id: u64,
+    split: Vec<PayoutShare>,
+) -> StdResult<Response> {
+    // get the signer which is the receiver of payment
+    let receiver = info.sender;
+
+    let parties = PartiesStore::load_for(deps.storage, id, &receiver)?;
+
+    // only the payee can choose where they are paid
+    if parties.payee != receiver.as_str() {
+        return Err(StdError::generic_err(
+            "You are not the payee of this invoice",
+        ));
+    }
+
+    let payer = deps.api.addr_validate(parties.payer.as_str())?;
+
+    // an empty split pays the payout address again
+    let mut total = 0u64;
+    let mut shares = vec![];
+    for share in split {
+        if share.basis_points == 0 {
+            return Err(StdError::generic_err("Payout shares must be positive"));
+        }
+
+        total += share.basis_points;
+        shares.push(PayoutShare {
+            address: deps.api.addr_validate(share.address.as_str())?.to_string(),
+            basis_points: share.basis_points,
+        });
+    }
+
+    if !shares.is_empty() && total != 10000 {
+        let error_message = format!(
+            "Payout shares add up to {} basis points instead of 10000",
+            total
+        );
+
+        return Err(StdError::generic_err(error_message));
+    }
+
+    // get the contract of specific id related to invoice
+    let mut contract = ContractStore::load_contract(deps.storage, &payer, id);
+    contract.invoice.payout_split = shares;
+
+    // save the update
+    InvoiceStore::save(deps.storage, &receiver, id, &contract.invoice)?;
+    ContractStore::save(deps.storage, &payer, id, &contract)?;
+
+    deps.api.debug("payout split updated successfully");
+    Ok(Response::new()
+        .add_attribute_plaintext("action", "set_payout_split")
+        .add_attribute_plaintext("invoice_id", id.to_string())
+        .add_attribute("shares", contract.invoice.payout_split.len().to_string()))
+}
+
+pub fn submit_milestone(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+    index: u32,
+    proof: String,
+) -> StdResult<Response> {
+    // get the signer which is the receiver of payment
+    let receiver = info.sender;
+
+    let parties = PartiesStore::load_for(deps.storage, id, &receiver)?;
+
+    // check that the signer is one that submitted the invoice
+    if parties.payee != receiver.as_str() {
+        return Err(StdError::generic_err(
+            "You are not the payee of this invoice",
+        ));
+    }
+
+    let payer = deps.api.addr_validate(parties.payer.as_str())?;
+
+    // get the contract of specific id related to invoice
+    let mut contract = ContractStore::load_contract(deps.storage, &payer, id);
+
+    if contract.contract_process != "started" {
+        return Err(StdError::generic_err("Invoice is not running"));
+    }
+
+    let milestone = match contract.invoice.milestones.get_mut(index as usize) {
+        Some(milestone) => milestone,
+        None => return Err(StdError::generic_err("Milestone not found")),
+    };
+
+    if milestone.status != MilestoneStatus::Pending {
+        return Err(StdError::generic_err(
+            "Milestone have already been submitted",
+        ));
+    }
+
+    milestone.status = MilestoneStatus::Submitted;
+    milestone.proof = Some(proof);
+    milestone.submitted_at = Some(env.block.time.seconds());
+
+    push_event(
+        deps.storage,
+        &env,
+        id,
+        &InvoiceEvent::new(
+            InvoiceEventKind::MilestoneSubmitted,
+            &receiver,
+            milestone.amount,
+            Uint128::zero(),
+            &contract.invoice.token,
+            &env,
+        ),
+    )?;
+
+    // save the update
+    InvoiceStore::save(deps.storage, &receiver, id, &contract.invoice)?;
+    ContractStore::save(deps.storage, &payer, id, &contract)?;
+
+    deps.api.debug("milestone submitted successfully");
+    Ok(Response::new()
+        .add_attribute_plaintext("action", "submit_milestone")
+        .add_attribute_plaintext("invoice_id", id.to_string())
+        .add_attribute("index", index.to_string()))
+}
+
+/// Releases a submitted milestone to the payee. The payer can approve it at any
+/// time, the payee once the review period has passed without an answer.
+pub fn approve_milestone(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+    index: u32,
+) -> StdResult<Response> {
+    // get the signer
+    let sender = info.sender;
+
+    let parties = PartiesStore::load_for(deps.storage, id, &sender)?;
+
+    if !parties.is_party(&sender) {
+        return Err(StdError::generic_err("You are not a party to this invoice"));
+    }
+
+    let receiver = deps.api.addr_validate(parties.payee.as_str())?;
+    let payer = deps.api.addr_validate(parties.payer.as_str())?;
+
+    // get the contract of specific id related to invoice
+    let mut contract = ContractStore::load_contract(deps.storage, &payer, id);
+
+    if contract.contract_process != "started" {
+        return Err(StdError::generic_err("Invoice is not running"));
+    }
+
+    if contract.dispute.is_some() {
+        return Err(StdError::generic_err("Invoice is under dispute"));
+    }
+
+    let current_block_time = env.block.time.seconds();
+    let review_period = contract.invoice.review_period_days * 86400;
+
+    if index as usize >= contract.invoice.milestones.len() {
+        return Err(StdError::generic_err("Milestone not found"));
+    }
+
+    let fee = contract.invoice.milestone_fee(index as usize);
+    let milestone = &mut contract.invoice.milestones[index as usize];
+
+    let submitted_at = match (milestone.status, milestone.submitted_at) {
+        (MilestoneStatus::Submitted, Some(submitted_at)) => submitted_at,
+        _ => {
+            return Err(StdError::generic_err(
+                "Milestone is not waiting for approval",
+            ))
+        }
+    };
+
+    // the payee can only approve it once the payer had the time to review it
+    if sender != payer && current_block_time < submitted_at + review_period {
+        return Err(StdError::generic_err(
+            "Milestone is still under review by the payer",
+        ));
+    }
+
+    milestone.status = MilestoneStatus::Approved;
+
+    let gross_amount = milestone.amount;
+    let fee = fee.min(gross_amount);
+
+    contract.account_balance -= gross_amount.u128();
+
+    // the invoice is done once every milestone is approved
+    if contract
+        .invoice
+        .milestones
+        .iter()
+        .all(|milestone| milestone.status == MilestoneStatus::Approved)
+    {
+        contract.invoice.remaining_time_of_payment = 0;
+        contract.invoice.status = "done".to_string();
+        contract.contract_process = "done".to_string();
+    }
+
+    // employee receive their payment, admin receive his changes
+    let messages = pay_out(
+        &mut deps,
+        &env,
+        &contract,
+        gross_amount,
+        fee,
+        InvoiceEventKind::MilestoneApproved,
+        &sender,
+    )?;
+
+    // save the update
+    InvoiceStore::save(deps.storage, &receiver, id, &contract.invoice)?;
+    ContractStore::save(deps.storage, &payer, id, &contract)?;
+
+    deps.api.debug("milestone approved successfully");
+    Ok(Response::new()
+        .add_messages(messages)
+        .add_attribute_plaintext("action", "approve_milestone")
+        .add_attribute_plaintext("invoice_id", id.to_string())
+        .add_attribute("index", index.to_string())
+        .add_attribute("amount", gross_amount.to_string())
+        .add_attribute("fee", fee.to_string())
+        .add_attribute("token", token_label(&contract.invoice.token)))
+}
+
+/// Freezes the escrow of an invoice until its arbiter resolves the dispute.
+pub fn raise_dispute(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+    reason: String,
+) -> StdResult<Response> {
+    // get the signer which is the payee or the payer
+    let sender = info.sender;
+
+    let parties = PartiesStore::load_for(deps.storage, id, &sender)?;
+
+    if !parties.is_party(&sender) {
+        return Err(StdError::generic_err("You are not a party to this invoice"));
+    }
+
+    let payer = deps.api.addr_validate(parties.payer.as_str())?;
+
+    // get the contract of specific id related to invoice
+    let mut contract = ContractStore::load_contract(deps.storage, &payer, id);
+
+    if !contract.contract_accepted || contract.account_balance == 0 {
+        return Err(StdError::generic_err("No payment reserved for this Invoice"));
+    }
+
+    if contract.dispute.is_some() {
+        return Err(StdError::generic_err("Invoice is already under dispute"));
+    }
+
+    if ArbiterStore::arbiter_of(deps.storage, &contract.invoice)?.is_none() {
+        return Err(StdError::generic_err("No arbiter to settle this invoice"));
+    }
+
+    contract.dispute = Some(Dispute {
+        raised_by: sender.to_string(),
+        reason,
+        raised_at: env.block.time.seconds(),
+        resolution: None,
+    });
+
+    // the terms under dispute can not be amended meanwhile
+    contract.pending_amendment = None;
+
+    push_event(
+        deps.storage,
+        &env,
+        id,
+        &InvoiceEvent::new(
+            InvoiceEventKind::DisputeRaised,
+            &sender,
+            Uint128::zero(),
+            Uint128::zero(),
+            &contract.invoice.token,
+            &env,
+        ),
+    )?;
+
+    // save the update
+    ContractStore::save(deps.storage, &payer, id, &contract)?;
+
+    deps.api.debug("dispute raised successfully");
+    Ok(Response::new()
+        .add_attribute_plaintext("action", "raise_dispute")
+        .add_attribute_plaintext("invoice_id", id.to_string())
+        .add_attribute("raised_by", sender.to_string()))
+}
+
+/// Distributes the whole escrow of a disputed invoice as decided by its arbiter
+/// and closes the invoice.
+pub fn resolve_dispute(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+    payee_share: Uint128,
+    payer_share: Uint128,
+) -> StdResult<Response> {
+    // get the signer which is the arbiter
+    let sender = info.sender;
+
+    let parties = PartiesStore::load_for(deps.storage, id, &sender)?;
+
+    let receiver = deps.api.addr_validate(parties.payee.as_str())?;
+    let payer = deps.api.addr_validate(parties.payer.as_str())?;
+
+    // get the contract of specific id related to invoice
+    let mut contract = ContractStore::load_contract(deps.storage, &payer, id);
+
+    let arbiter = ArbiterStore::arbiter_of(deps.storage, &contract.invoice)?;
+    if arbiter.as_deref() != Some(sender.as_str()) {
+        return Err(StdError::generic_err(
+            "You are not the arbiter of this invoice",
+        ));
+    }
+
+    let mut dispute = match contract.dispute.take() {
+        Some(dispute) => dispute,
+        None => return Err(StdError::generic_err("Invoice is not under dispute")),
+    };
+
+    if payee_share + payer_share != Uint128::from(contract.account_balance) {
+        let error_message = format!(
+            "Shares must add up to the escrow balance: {}",
+            contract.account_balance
+        );
+
+        return Err(StdError::generic_err(error_message));
+    }
+
+    let current_block_time = env.block.time.seconds();
+
+    dispute.resolution = Some(DisputeResolution {
+        arbiter: sender.to_string(),
+        payee_share,
+        payer_share,
+        resolved_at: current_block_time,
+    });
+    DisputeStore::push(deps.storage, id, &dispute)?;
+
+    let token = contract.invoice.token.clone();
+
+    // the payee share is paid without the customer charge
+    let messages = pay_out(
+        &mut deps,
+        &env,
+        &contract,
+        payee_share,
+        Uint128::zero(),
+        InvoiceEventKind::DisputeResolved,
+        &sender,
+    )?;
+    if !payer_share.is_zero() {
+        push_event(
+            deps.storage,
+            &env,
+            id,
+            &InvoiceEvent::new(
+                InvoiceEventKind::Refunded,
+                &payer,
+                payer_share,
+                Uint128::zero(),
+                &token,
+                &env,
+            ),
+        )?;
+    }
+
+    // the escrow is settled, nothing is left to pay
+    contract.invoice.payment_condition = "no".to_string();
+    contract.invoice.status = "stop".to_string();
+    contract.invoice.remaining_time_of_payment = 0;
+    contract.contract_process = "stop".to_string();
+    contract.account_balance = 0;
+    contract.accrued_penalty = Uint128::zero();
+
+    // save the update
+    InvoiceStore::save(deps.storage, &receiver, id, &contract.invoice)?;
+    ContractStore::save(deps.storage, &payer, id, &contract)?;
+
+    let denom = contract.invoice.denom()?;
+
+    deps.api.debug("dispute resolved successfully");
+    Ok(Response::new()
+        .add_messages(messages)
+        .add_messages(bank_send(&payer, payer_share.u128(), &denom))
+        .add_attribute_plaintext("action", "resolve_dispute")
+        .add_attribute_plaintext("invoice_id", id.to_string())
+        .add_attribute("payee_share", payee_share.to_string())
+        .add_attribute("payer_share", payer_share.to_string())
+        .add_attribute("token", token_label(&token)))
+}
+
+pub fn set_arbiter(deps: DepsMut, info: MessageInfo, arbiter: String) -> StdResult<Response> {
+    let admin_wallet = AdminStore::get_admin_wallet(deps.storage);
+
+    // check the signer is admin
+    if info.sender != admin_wallet {
+        return Err(StdError::generic_err("Admin role only"));
+    }
+
+    let arbiter = deps.api.addr_validate(arbiter.as_str())?;
+    ArbiterStore::save(deps.storage, &arbiter)?;
+
+    deps.api.debug("arbiter save successfully");
+    Ok(Response::new()
+        .add_attribute_plaintext("action", "set_arbiter")
+        .add_attribute("arbiter", arbiter.to_string()))
+}
+
+/// Deletes up to `limit` invoices left unaccepted past their expiry time.
+pub fn purge_expired(deps: DepsMut, env: Env, limit: Option<u32>) -> StdResult<Response> {
+    let limit = limit.unwrap_or(10).min(100) as usize;
+
+    let ids = ExpiryStore::expired(deps.storage, env.block.time.seconds(), limit)?;
+
+    for id in &ids {
+        let parties = PartiesStore::load(deps.storage, *id)?;
+        let payee = deps.api.addr_validate(parties.payee.as_str())?;
+        let payer = deps.api.addr_validate(parties.payer.as_str())?;
+
+        let contract = ContractStore::load_contract(deps.storage, &payer, *id);
+        if let Some(organization) = contract.invoice.organization {
+            OrganizationStore::remove_invoice(deps.storage, organization, *id)?;
+        }
+
+        InvoiceStore::remove(deps.storage, &payee, *id)?;
+        ContractStore::remove(deps.storage, &payer, *id)?;
+        PartiesStore::remove(deps.storage, *id)?;
+        HistoryStore::remove(deps.storage, *id)?;
+        ExpiryStore::remove(deps.storage, *id)?;
+        TagStore::remove(deps.storage, *id)?;
+        MemoStore::remove(deps.storage, *id)?;
+        LineItemStore::remove(deps.storage, *id)?;
+    }
+
+    deps.api.debug("expired invoices purged successfully");
+    Ok(Response::new()
+        .add_attribute_plaintext("action", "purge_expired")
+        .add_attribute_plaintext("purged", ids.len().to_string()))
+}
+
+pub fn deposit_treasury(mut deps: DepsMut, env: Env, info: MessageInfo) -> StdResult<Response> {
+    // get the signer which is the payer
+    let payer = info.sender;
+
+    let amount = deposit_of(&info.funds, TREASURY_DENOM)?;
+
+    if amount.is_zero() {
+        return Err(StdError::generic_err("Insufficient token attach"));
+    }
+
+    // late penalty owed until now is paid to the payees out of the deposit first
+    let previous = TreasuryStore::load(deps.storage, &payer);
+    let (penalties, penalty_msgs) =
+        settle_treasury_penalties(&mut deps, &env, &payer, previous, previous + amount)?;
+
+    let balance = previous + amount - penalties;
+    TreasuryStore::save(deps.storage, &payer, balance)?;
+
+    deps.api.debug("treasury deposit successfully");
+    Ok(Response::new()
+        .add_messages(penalty_msgs)
+        .add_attribute_plaintext("action", "deposit_treasury")
+        .add_attribute("amount", amount.to_string())
+        .add_attribute("penalty", penalties.to_string())
+        .add_attribute("balance", balance.to_string()))
+}
+
+pub fn withdraw_treasury(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+) -> StdResult<Response> {
+    // get the signer which is the payer
+    let payer = info.sender;
+
+    // late penalty owed on contracts drawing from the treasury is settled
+    // before the payer takes funds back
+    let balance = TreasuryStore::load(deps.storage, &payer);
+    let (penalties, penalty_msgs) =
+        settle_treasury_penalties(&mut deps, &env, &payer, balance, balance)?;
+    let balance = balance - penalties;
+
+    if amount.is_zero() || amount > balance {
+        let error_message = format!(
+            "Amount {} is not available in treasury. Balance: {}",
+            amount, balance
+        );
+
+        return Err(StdError::generic_err(error_message));
+    }
+
+    let balance = balance - amount;
+    TreasuryStore::save(deps.storage, &payer, balance)?;
+
+    let denom = TREASURY_DENOM.to_string();
+
+    deps.api.debug("treasury withdraw successfully");
+    Ok(Response::new()
+        .add_messages(penalty_msgs)
+        .add_messages(bank_send(&payer, amount.u128(), &denom))
+        .add_attribute_plaintext("action", "withdraw_treasury")
+        .add_attribute("amount", amount.to_string())
+        .add_attribute("penalty", penalties.to_string())
+        .add_attribute("balance", balance.to_string()))
+}
+
+pub fn admin_change_admin(deps: DepsMut, env: Env, info: MessageInfo, admin: String) -> StdResult<Response> {
+    // get the signer which is the payer
+    let sender = info.sender;
+
+    let admin_wallet = AdminStore::get_admin_wallet( deps.storage);
+
+    let admin_wallet_validate = deps.api.addr_validate(admin_wallet.as_str())?;
+
+    // check the signer ias admin
+    if sender != admin_wallet_validate {
+        return Err(StdError::generic_err(
             "Admin role only",
         ));
     }
@@ -485,6 +2815,370 @@ pub fn admin_change_admin(deps: DepsMut, env: Env, info: MessageInfo, admin: Str
     AdminStore::update_admin_wallet(deps.storage, &new_admin_address)?;
 
     deps.api.debug("new admin save successfully");
-    Ok(Response::default())
+    Ok(Response::new()
+        .add_attribute_plaintext("action", "update_admin")
+        .add_attribute("admin", new_admin_address.to_string()))
+}
+
+
+pub fn grant_operator(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    operator: String,
+    permissions: Vec<OperatorPermission>,
+    expires: Option<u64>,
+) -> StdResult<Response> {
+    // get the signer which is the payer
+    let payer = info.sender;
+
+    let operator = deps.api.addr_validate(operator.as_str())?;
+
+    if operator == payer {
+        return Err(StdError::generic_err("You can not be your own operator"));
+    }
+
+    if permissions.is_empty() {
+        return Err(StdError::generic_err("Operator needs at least one permission"));
+    }
+
+    if let Some(expires) = expires {
+        if expires <= env.block.time.seconds() {
+            return Err(StdError::generic_err("Grant would already be expired"));
+        }
+    }
+
+    // a new grant replaces the previous one
+    let grant = Operator {
+        permissions,
+        expires,
+    };
+    OperatorStore::save(deps.storage, &payer, &operator, &grant)?;
+
+    deps.api.debug("operator granted successfully");
+    Ok(Response::new()
+        .add_attribute_plaintext("action", "grant_operator")
+        .add_attribute("operator", operator.to_string()))
+}
+
+pub fn revoke_operator(deps: DepsMut, info: MessageInfo, operator: String) -> StdResult<Response> {
+    // get the signer which is the payer
+    let payer = info.sender;
+
+    let operator = deps.api.addr_validate(operator.as_str())?;
+
+    if !OperatorStore::remove(deps.storage, &payer, &operator)? {
+        return Err(StdError::generic_err("Address is not your operator"));
+    }
+
+    deps.api.debug("operator revoked successfully");
+    Ok(Response::new()
+        .add_attribute_plaintext("action", "revoke_operator")
+        .add_attribute("operator", operator.to_string()))
+}
+
+pub fn create_organization(deps: DepsMut, info: MessageInfo, name: String) -> StdResult<Response> {
+    // get the signer which is the owner of the organization
+    let owner = info.sender;
+
+    let id = get_next_organization_id(deps.storage)?;
+
+    let organization = Organization {
+        id,
+        name,
+        owner: owner.to_string(),
+        admins: vec![],
+        members: vec![],
+    };
+    OrganizationStore::save(deps.storage, &organization)?;
+
+    deps.api.debug("organization created successfully");
+    Ok(Response::new()
+        .add_attribute_plaintext("action", "create_organization")
+        .add_attribute_plaintext("organization_id", id.to_string()))
+}
+
+pub fn add_organization_member(
+    mut deps: DepsMut,
+    info: MessageInfo,
+    id: u64,
+    member: String,
+    admin: bool,
+) -> StdResult<Response> {
+    let sender = info.sender;
+
+    let mut organization = OrganizationStore::load(deps.storage, id)?;
+
+    // the owner appoints admins, admins manage the members
+    let allowed = match admin {
+        true => organization.owner == sender.as_str(),
+        false => organization.is_admin(&sender),
+    };
+    if !allowed {
+        return Err(StdError::generic_err(
+            "You are not allowed to manage this organization",
+        ));
+    }
+
+    let member = deps.api.addr_validate(member.as_str())?;
+
+    if organization.owner == member.as_str() {
+        return Err(StdError::generic_err("Owner is already part of the organization"));
+    }
+
+    // a member can be promoted to admin and an admin demoted to member
+    let demoted = !admin && organization.is_admin(&member);
+    organization.admins.retain(|address| address != member.as_str());
+    organization.members.retain(|address| address != member.as_str());
+
+    match admin {
+        true => organization.admins.push(member.to_string()),
+        false => organization.members.push(member.to_string()),
+    }
+    OrganizationStore::save(deps.storage, &organization)?;
+
+    if demoted {
+        release_organization_invoices(&mut deps, &organization, &member)?;
+    }
+
+    deps.api.debug("organization member added successfully");
+    Ok(Response::new()
+        .add_attribute_plaintext("action", "add_organization_member")
+        .add_attribute_plaintext("organization_id", id.to_string())
+        .add_attribute("member", member.to_string())
+        .add_attribute("admin", admin.to_string()))
+}
+
+pub fn remove_organization_member(
+    mut deps: DepsMut,
+    info: MessageInfo,
+    id: u64,
+    member: String,
+) -> StdResult<Response> {
+    let sender = info.sender;
+
+    let mut organization = OrganizationStore::load(deps.storage, id)?;
+
+    let member = deps.api.addr_validate(member.as_str())?;
+
+    // the owner removes admins, admins remove the members
+    let is_admin = organization.admins.iter().any(|admin| admin == member.as_str());
+    let allowed = match is_admin {
+        true => organization.owner == sender.as_str(),
+        false => organization.is_admin(&sender),
+    };
+    if !allowed {
+        return Err(StdError::generic_err(
+            "You are not allowed to manage this organization",
+        ));
+    }
+
+    if !organization.is_member(&member) || organization.owner == member.as_str() {
+        return Err(StdError::generic_err(
+            "Address is not a removable member of this organization",
+        ));
+    }
+
+    organization.admins.retain(|address| address != member.as_str());
+    organization.members.retain(|address| address != member.as_str());
+    OrganizationStore::save(deps.storage, &organization)?;
+
+    // the invoices a removed admin paid are paid by the owner from then on
+    if is_admin {
+        release_organization_invoices(&mut deps, &organization, &member)?;
+    }
+
+    deps.api.debug("organization member removed successfully");
+    Ok(Response::new()
+        .add_attribute_plaintext("action", "remove_organization_member")
+        .add_attribute_plaintext("organization_id", id.to_string())
+        .add_attribute("member", member.to_string()))
+}
+
+pub fn set_tags(deps: DepsMut, info: MessageInfo, id: u64, tags: Vec<Tag>) -> StdResult<Response> {
+    // get the signer which is the payer
+    let payer = info.sender;
+
+    let parties = PartiesStore::load_for(deps.storage, id, &payer)?;
+
+    // tags break down the spend of the payer, only they can set them
+    if parties.payer != payer.as_str() {
+        return Err(StdError::generic_err(
+            "You are not the payer of this invoice",
+        ));
+    }
+
+    if tags.len() > MAX_TAGS {
+        let error_message = format!("An invoice can have at most {} tags", MAX_TAGS);
+
+        return Err(StdError::generic_err(error_message));
+    }
+
+    for (index, tag) in tags.iter().enumerate() {
+        if tag.key.is_empty() || tags[..index].iter().any(|other| other.key == tag.key) {
+            return Err(StdError::generic_err("Tag keys must be set and distinct"));
+        }
+    }
+
+    TagStore::save(deps.storage, id, &tags)?;
+
+    deps.api.debug("tags set successfully");
+    Ok(Response::new()
+        .add_attribute_plaintext("action", "set_tags")
+        .add_attribute_plaintext("invoice_id", id.to_string()))
+}
+
+pub fn set_viewing_key(deps: DepsMut, info: MessageInfo, key: String) -> StdResult<Response> {
+    ViewingKey::set(deps.storage, info.sender.as_str(), key.as_str());
+
+    deps.api.debug("viewing key set successfully");
+    Ok(Response::new().add_attribute_plaintext("action", "set_viewing_key"))
+}
+
+pub fn revoke_permit(deps: DepsMut, info: MessageInfo, permit_name: String) -> StdResult<Response> {
+    RevokedPermits::revoke_permit(
+        deps.storage,
+        PREFIX_REVOKED_PERMITS,
+        info.sender.as_str(),
+        permit_name.as_str(),
+    );
+
+    deps.api.debug("permit revoked successfully");
+    Ok(Response::new().add_attribute_plaintext("action", "revoke_permit"))
+}
+
+pub fn set_memo(
+    deps: DepsMut,
+    info: MessageInfo,
+    id: u64,
+    memo: Option<String>,
+) -> StdResult<Response> {
+    let sender = info.sender;
+
+    let parties = PartiesStore::load_for(deps.storage, id, &sender)?;
+
+    // the memo is a note between the parties
+    if !parties.is_party(&sender) {
+        return Err(StdError::generic_err("You are not a party to this invoice"));
+    }
+
+    match memo {
+        Some(memo) => MemoStore::save(deps.storage, id, &memo)?,
+        None => MemoStore::remove(deps.storage, id)?,
+    }
+
+    deps.api.debug("memo set successfully");
+    Ok(Response::new()
+        .add_attribute_plaintext("action", "set_memo")
+        .add_attribute_plaintext("invoice_id", id.to_string()))
+}
+
+pub fn set_withholding(
+    deps: DepsMut,
+    info: MessageInfo,
+    id: u64,
+    withholding: Option<Withholding>,
+) -> StdResult<Response> {
+    // get the signer which is the payer
+    let payer = info.sender;
+
+    let parties = PartiesStore::load_for(deps.storage, id, &payer)?;
+
+    if parties.payer != payer.as_str() {
+        return Err(StdError::generic_err(
+            "You are not the payer of this invoice",
+        ));
+    }
+
+    let receiver = deps.api.addr_validate(parties.payee.as_str())?;
+
+    // get the contract of specific id related to invoice
+    let mut contract = ContractStore::load_contract(deps.storage, &payer, id);
+
+    // the payee has to agree to the withholding again before the invoice is accepted
+    if contract.contract_accepted {
+        return Err(StdError::generic_err("Invoice have already been accepted"));
+    }
+
+    let withholding = match withholding {
+        Some(withholding) => {
+            if withholding.basis_points > 10000 {
+                return Err(StdError::generic_err(
+                    "withholding rate can not exceed 10000 basis points",
+                ));
+            }
+
+            Some(Withholding {
+                basis_points: withholding.basis_points,
+                authority: deps
+                    .api
+                    .addr_validate(withholding.authority.as_str())?
+                    .to_string(),
+                agreed: false,
+            })
+        }
+        None => None,
+    };
+    contract.invoice.withholding = withholding;
+
+    // save the update
+    InvoiceStore::save(deps.storage, &receiver, id, &contract.invoice)?;
+    ContractStore::save(deps.storage, &payer, id, &contract)?;
+
+    deps.api.debug("withholding set successfully");
+    Ok(Response::new()
+        .add_attribute_plaintext("action", "set_withholding")
+        .add_attribute_plaintext("invoice_id", id.to_string()))
 }
 
+pub fn agree_withholding(
+    deps: DepsMut,
+    info: MessageInfo,
+    id: u64,
+    withholding: Withholding,
+) -> StdResult<Response> {
+    // get the signer which is the receiver of payment
+    let receiver = info.sender;
+
+    let parties = PartiesStore::load_for(deps.storage, id, &receiver)?;
+
+    if parties.payee != receiver.as_str() {
+        return Err(StdError::generic_err(
+            "You are not the payee of this invoice",
+        ));
+    }
+
+    let payer = deps.api.addr_validate(parties.payer.as_str())?;
+
+    // get the contract of specific id related to invoice
+    let mut contract = ContractStore::load_contract(deps.storage, &payer, id);
+
+    if contract.contract_accepted {
+        return Err(StdError::generic_err("Invoice have already been accepted"));
+    }
+
+    // the payee agrees to the withholding they have seen, not to a later one
+    let current = match contract.invoice.withholding.as_mut() {
+        Some(current)
+            if current.basis_points == withholding.basis_points
+                && current.authority == withholding.authority =>
+        {
+            current
+        }
+        _ => {
+            return Err(StdError::generic_err(
+                "Withholding does not match the one set by the payer",
+            ))
+        }
+    };
+    current.agreed = true;
+
+    // save the update
+    InvoiceStore::save(deps.storage, &receiver, id, &contract.invoice)?;
+    ContractStore::save(deps.storage, &payer, id, &contract)?;
+
+    deps.api.debug("withholding agreed successfully");
+    Ok(Response::new()
+        .add_attribute_plaintext("action", "agree_withholding")
+        .add_attribute_plaintext("invoice_id", id.to_string()))
+}
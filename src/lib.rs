@@ -1,4 +1,5 @@
 pub mod contract;
 mod execute;
 mod msg;
+mod query;
 mod state;
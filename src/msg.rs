@@ -1,39 +1,172 @@
 #![allow(clippy::field_reassign_with_default)] // This is triggered in `#[derive(JsonSchema)]`
 
-use cosmwasm_std::{Addr, Uint128};
+use cosmwasm_std::{Addr, Decimal, Uint128};
 use schemars::JsonSchema;
+use secret_toolkit::permit::Permit;
 use secret_toolkit::utils::types::Token;
 use serde::{Deserialize, Serialize};
 
+use crate::state::{
+    CancellationPolicy, Contract, Dispute, EarlyPaymentTerms, Invoice, InvoiceEvent, InvoiceStatus,
+    LatePaymentTerms, LineItem, MilestonePlan, OperatorPermission, PayoutShare, PayrollRow, SortBy,
+    Tag, TagSpend, Withdrawal, Withholding,
+};
+
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
 pub struct InstantiateMsg {}
 
+/// Terms of a new invoice, submitted by its payee.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[cfg_attr(test, derive(Eq, PartialEq))]
+pub struct SubmitInvoiceMsg {
+    pub purpose: String,
+    pub amount: u128,
+    pub admin_charge: Uint128,
+    pub customer_charge: Uint128,
+    /// optional when an organization is billed, its owner holds the invoice until an
+    /// admin accepts it
+    pub payer: Option<String>,
+    pub days: u64,
+    pub recurrent_time: Option<u64>,
+    pub token: Token,
+    pub late_payment: Option<LatePaymentTerms>,
+    pub early_payment: Option<EarlyPaymentTerms>,
+    pub milestones: Option<MilestonePlan>,
+    pub arbiter: Option<String>,
+    pub cancellation_policy: Option<CancellationPolicy>,
+    pub expires_at: Option<u64>,
+    pub valid_for_days: Option<u64>,
+    pub payout_address: Option<String>,
+    pub funding_tokens: Option<Vec<Token>>,
+    pub organization: Option<u64>,
+    pub line_items: Option<Vec<LineItem>>,
+    pub memo: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[cfg_attr(test, derive(Eq, PartialEq))]
 #[serde(rename_all = "snake_case")]
+#[allow(clippy::large_enum_variant)]
 pub enum ExecuteMsg {
-    SubmitInvoice {
-        purpose: String,
-        amount: u128,
-        admin_charge: Uint128,
-        customer_charge: Uint128,
-        payer: String,
-        days: u64,
-        recurrent_time: Option<u64>,
+    SubmitInvoice(SubmitInvoiceMsg),
+    SetFundingRate {
+        id: u64,
+        token: Token,
+        rate: Decimal,
+    },
+    AgreeFundingRate {
+        id: u64,
         token: Token,
+        rate: Decimal,
     },
     AcceptInvoice {
         id: u64,
     },
+    TopUp {
+        id: u64,
+    },
+    SubmitMilestone {
+        id: u64,
+        index: u32,
+        proof: String,
+    },
+    ApproveMilestone {
+        id: u64,
+        index: u32,
+    },
     CancelPayment {
         id: u64,
     },
+    TerminateWithNotice {
+        id: u64,
+    },
+    Resign {
+        id: u64,
+    },
+    ProposeAmendment {
+        id: u64,
+        new_amount: Uint128,
+        new_period: u64,
+        new_count: u64,
+    },
+    AcceptAmendment {
+        id: u64,
+    },
     WithdrawPayment {
         id: u64,
     },
+    SetPayoutAddress {
+        id: u64,
+        payout_address: Option<String>,
+    },
+    SetPayoutSplit {
+        id: u64,
+        split: Vec<PayoutShare>,
+    },
+    RaiseDispute {
+        id: u64,
+        reason: String,
+    },
+    ResolveDispute {
+        id: u64,
+        payee_share: Uint128,
+        payer_share: Uint128,
+    },
+    SetArbiter {
+        arbiter: String,
+    },
     AdminUpdateAmin{
         newAdmin: String,
-    }
+    },
+    PurgeExpired {
+        limit: Option<u32>,
+    },
+    DepositTreasury {},
+    WithdrawTreasury {
+        amount: Uint128,
+    },
+    GrantOperator {
+        operator: String,
+        permissions: Vec<OperatorPermission>,
+        expires: Option<u64>,
+    },
+    RevokeOperator {
+        operator: String,
+    },
+    CreateOrganization {
+        name: String,
+    },
+    AddOrganizationMember {
+        id: u64,
+        member: String,
+        admin: bool,
+    },
+    RemoveOrganizationMember {
+        id: u64,
+        member: String,
+    },
+    SetTags {
+        id: u64,
+        tags: Vec<Tag>,
+    },
+    SetViewingKey {
+        key: String,
+    },
+    RevokePermit {
+        permit_name: String,
+    },
+    SetMemo {
+        id: u64,
+        memo: Option<String>,
+    },
+    SetWithholding {
+        id: u64,
+        withholding: Option<Withholding>,
+    },
+    AgreeWithholding {
+        id: u64,
+        withholding: Withholding,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
@@ -66,4 +199,240 @@ pub enum QueryMsg {
         page_size: u32,
     },
     AdmimWallet {},
+    FilteredInvoice {
+        owner: Addr,
+        filter: Option<ListingFilter>,
+        sort_by: Option<SortBy>,
+        descending: Option<bool>,
+        start_after: Option<ListingCursor>,
+        limit: Option<u32>,
+    },
+    FilteredContract {
+        payer: Addr,
+        filter: Option<ListingFilter>,
+        sort_by: Option<SortBy>,
+        descending: Option<bool>,
+        start_after: Option<ListingCursor>,
+        limit: Option<u32>,
+    },
+    PayerObligations {
+        payer: Addr,
+        from: u64,
+        to: u64,
+    },
+    PayeeStatement {
+        payee: Addr,
+        from: u64,
+        to: u64,
+        token: Token,
+        page: u32,
+        auth: QueryAuth,
+    },
+    InvoiceHistory {
+        id: u64,
+        address: Addr,
+        page: u32,
+        page_size: u32,
+        auth: QueryAuth,
+    },
+    FundingStatus {
+        id: u64,
+        payer: Addr,
+    },
+    Treasury {
+        payer: Addr,
+    },
+    Dispute {
+        id: u64,
+        address: Addr,
+        auth: QueryAuth,
+    },
+    Organization {
+        id: u64,
+        address: Addr,
+        auth: QueryAuth,
+    },
+    OrganizationContracts {
+        id: u64,
+        address: Addr,
+        page: u32,
+        page_size: u32,
+        auth: QueryAuth,
+    },
+    Tags {
+        id: u64,
+        payer: Addr,
+        auth: QueryAuth,
+    },
+    SpendByTag {
+        payer: Addr,
+        tag: Tag,
+        from: u64,
+        to: u64,
+        auth: QueryAuth,
+    },
+    PayrollReport {
+        payer: Addr,
+        from: u64,
+        to: u64,
+        page: u32,
+        auth: QueryAuth,
+    },
+    Memo {
+        id: u64,
+        address: Addr,
+        auth: QueryAuth,
+    },
+}
+
+/// Optional criteria for the filtered listing queries, all of which must match.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
+#[cfg_attr(test, derive(Eq, PartialEq))]
+pub struct ListingFilter {
+    pub status: Option<InvoiceStatus>,
+    pub token: Option<Token>,
+    /// inclusive lower bound on the payment time
+    pub due_from: Option<u64>,
+    /// inclusive upper bound on the payment time
+    pub due_to: Option<u64>,
+    /// payer when listing invoices, payee when listing contracts
+    pub counterparty: Option<String>,
+}
+
+/// Position where a page stopped. Passing it back as `start_after` resumes
+/// right after it, even if new invoices were submitted since.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, JsonSchema)]
+pub struct ListingCursor {
+    pub key: Uint128,
+    pub id: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct InvoiceListResponse {
+    pub invoices: Vec<Invoice>,
+    pub next: Option<ListingCursor>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct ContractListResponse {
+    pub contracts: Vec<Contract>,
+    pub next: Option<ListingCursor>,
+}
+
+/// A scheduled payment of an accepted contract.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Installment {
+    pub invoice_id: u64,
+    /// zero-based position of the payment in the contract schedule
+    pub index: u64,
+    pub due_time: u64,
+    pub amount: Uint128,
+    pub token: Token,
+    pub payee: String,
+    /// whether the contract balance already covers this payment
+    pub funded: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct TokenTotal {
+    pub token: Token,
+    pub amount: Uint128,
+    pub funded: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct PayerObligationsResponse {
+    pub installments: Vec<Installment>,
+    pub totals: Vec<TokenTotal>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct PayeeStatementResponse {
+    pub withdrawals: Vec<Withdrawal>,
+    /// number of withdrawals in the period over all pages, in any token
+    pub total: u32,
+    pub total_gross_amount: Uint128,
+    pub total_fee: Uint128,
+    pub total_withheld: Uint128,
+    pub total_net_amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct InvoiceHistoryResponse {
+    pub events: Vec<InvoiceEvent>,
+    pub total: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct FundingStatusResponse {
+    /// number of payments of the invoice
+    pub installments: u64,
+    /// payments already withdrawn
+    pub paid: u64,
+    /// payments deposited for so far, withdrawn or not
+    pub funded: u64,
+    /// payments the balance still covers
+    pub covered: u64,
+    pub account_balance: Uint128,
+    /// due time of the next payment, if any is left
+    pub next_due_time: Option<u64>,
+    pub fully_funded: bool,
+    pub treasury_balance: Uint128,
+    /// neither the balance nor the payer treasury can pay the next payment
+    pub low_balance: bool,
+    /// time from which the next payment is late, if the balance does not cover it
+    pub overdue_since: Option<u64>,
+    /// late penalty owed to the payee up to now
+    pub accrued_penalty: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct TreasuryResponse {
+    pub balance: Uint128,
+    /// what the next payments of the active contracts need from the treasury
+    pub next_payments: Uint128,
+    pub low_balance: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[cfg_attr(test, derive(Eq, PartialEq))]
+pub struct DisputeResponse {
+    pub arbiter: Option<String>,
+    /// dispute waiting for the arbiter
+    pub open: Option<Dispute>,
+    /// resolved disputes, oldest first
+    pub history: Vec<Dispute>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct OrganizationContractsResponse {
+    pub contracts: Vec<Contract>,
+    pub total: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct SpendByTagResponse {
+    pub totals: Vec<TagSpend>,
+}
+
+/// Proof that the querier owns the queried address.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryAuth {
+    ViewingKey { key: String },
+    Permit { permit: Permit },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct PayrollReportResponse {
+    pub rows: Vec<PayrollRow>,
+    /// number of rows in the period over all pages
+    pub total: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct MemoResponse {
+    pub memo: Option<String>,
+    /// breakdown of the amount, empty if the invoice was submitted without one
+    pub line_items: Vec<LineItem>,
 }
@@ -0,0 +1,671 @@
+use cosmwasm_std::{Addr, Deps, Env, StdError, StdResult, Uint128};
+use secret_toolkit::permit::{validate, TokenPermissions};
+use secret_toolkit::utils::types::Token;
+use secret_toolkit::viewing_key::{ViewingKey, ViewingKeyStore};
+
+use crate::msg::{
+    ContractListResponse, DisputeResponse, FundingStatusResponse, Installment,
+    InvoiceHistoryResponse, InvoiceListResponse, ListingCursor, ListingFilter, MemoResponse,
+    OrganizationContractsResponse, PayeeStatementResponse, PayerObligationsResponse,
+    PayrollReportResponse, QueryAuth, SpendByTagResponse, TokenTotal, TreasuryResponse,
+};
+use crate::state::{
+    ArbiterStore, Contract, ContractStore, DisputeStore, HistoryStore, IndexEntry, Invoice,
+    InvoiceStatus, InvoiceStore, LineItemStore, ListingIndex, ListingScope, MemoStore,
+    MilestoneStatus, Organization, OrganizationStore, PartiesStore, PayrollStore, SortBy, Tag,
+    TagSpendStore, TagStore, TreasuryStore, WithdrawalStore, PAYEE_ROLE, PAYER_ROLE,
+    PREFIX_REVOKED_PERMITS, TREASURY_DENOM,
+};
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 100;
+const PAYROLL_PAGE_SIZE: u32 = 100;
+const STATEMENT_PAGE_SIZE: u32 = 100;
+// index entries read per listing page, matching or not
+const MAX_SCAN: usize = 300;
+
+impl ListingFilter {
+    fn matches(&self, invoice: &Invoice, counterparty: &str) -> bool {
+        if let Some(status) = self.status {
+            if status != InvoiceStatus::of(invoice) {
+                return false;
+            }
+        }
+
+        if let Some(token) = &self.token {
+            if token != &invoice.token {
+                return false;
+            }
+        }
+
+        if let Some(due_from) = self.due_from {
+            if invoice.payment_time < due_from {
+                return false;
+            }
+        }
+
+        if let Some(due_to) = self.due_to {
+            if invoice.payment_time > due_to {
+                return false;
+            }
+        }
+
+        match &self.counterparty {
+            Some(address) => address == counterparty,
+            None => true,
+        }
+    }
+
+    /// The most selective listing index covering the filter.
+    fn scope(&self) -> ListingScope<'_> {
+        match (&self.counterparty, &self.token, self.status) {
+            (Some(address), _, _) => ListingScope::Counterparty(address),
+            (None, Some(token), _) => ListingScope::Token(token),
+            (None, None, Some(status)) => ListingScope::Status(status),
+            (None, None, None) => ListingScope::All,
+        }
+    }
+}
+
+fn cursor_of(entry: IndexEntry) -> ListingCursor {
+    ListingCursor {
+        key: Uint128::new(entry.key),
+        id: entry.id,
+    }
+}
+
+/// Walks the listing index of `owner` from `start_after` and returns the
+/// items matching `filter`, together with the cursor to request the next
+/// page with. At most `MAX_SCAN` entries are read per page; a page cut
+/// short returns the cursor of the last entry read.
+#[allow(clippy::too_many_arguments)]
+fn listing_page<T, L, I>(
+    deps: Deps,
+    role: &[u8],
+    owner: &Addr,
+    filter: &ListingFilter,
+    sort_by: SortBy,
+    descending: bool,
+    start_after: Option<ListingCursor>,
+    limit: Option<u32>,
+    now: u64,
+    load: L,
+    invoice_of: I,
+) -> StdResult<(Vec<T>, Option<ListingCursor>)>
+where
+    L: Fn(u64) -> T,
+    I: Fn(&T) -> &Invoice,
+{
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let index = ListingIndex::index(role, owner, &filter.scope(), sort_by)?;
+
+    let mut start = start_after.map(|cursor| IndexEntry {
+        key: cursor.key.u128(),
+        id: cursor.id,
+    });
+
+    // the due date index starts right at the due date window
+    if sort_by == SortBy::DueDate {
+        match (descending, filter.due_from, filter.due_to) {
+            (false, Some(due_from), _) => {
+                let bound = IndexEntry {
+                    key: u128::from(due_from),
+                    id: 0,
+                };
+                start = start.max(Some(bound));
+            }
+            (true, _, Some(due_to)) => {
+                let bound = IndexEntry {
+                    key: u128::from(due_to) + 1,
+                    id: 0,
+                };
+                start = Some(start.unwrap_or(bound).min(bound));
+            }
+            _ => {}
+        }
+    }
+
+    let mut items: Vec<T> = vec![];
+    let mut next = None;
+    let mut last_item = None;
+    let mut last_read = None;
+    let mut read = 0;
+    index.visit(deps.storage, start, descending, |entry| {
+        if sort_by == SortBy::DueDate {
+            let past_window = match descending {
+                false => matches!(filter.due_to, Some(due_to) if entry.key > u128::from(due_to)),
+                true => {
+                    matches!(filter.due_from, Some(due_from) if entry.key < u128::from(due_from))
+                }
+            };
+            if past_window {
+                return Ok(false);
+            }
+        }
+
+        if read == MAX_SCAN {
+            next = last_read.map(cursor_of);
+            return Ok(false);
+        }
+        read += 1;
+        last_read = Some(entry);
+
+        let item = load(entry.id);
+        let invoice = invoice_of(&item);
+        let counterparty = match role == PAYEE_ROLE {
+            true => &invoice.payer,
+            false => &invoice.receiver,
+        };
+        if invoice.is_expired(now) || !filter.matches(invoice, counterparty) {
+            return Ok(true);
+        }
+
+        // one more match means there is a next page
+        if items.len() == limit {
+            next = last_item.map(cursor_of);
+            return Ok(false);
+        }
+
+        items.push(item);
+        last_item = Some(entry);
+        Ok(true)
+    })?;
+
+    Ok((items, next))
+}
+
+/// Invoices left unaccepted past their expiry time are left out.
+#[allow(clippy::too_many_arguments)]
+pub fn filtered_invoices(
+    deps: Deps,
+    env: Env,
+    owner: Addr,
+    filter: Option<ListingFilter>,
+    sort_by: Option<SortBy>,
+    descending: Option<bool>,
+    start_after: Option<ListingCursor>,
+    limit: Option<u32>,
+) -> StdResult<InvoiceListResponse> {
+    let (invoices, next) = listing_page(
+        deps,
+        PAYEE_ROLE,
+        &owner,
+        &filter.unwrap_or_default(),
+        sort_by.unwrap_or(SortBy::InvoiceId),
+        descending.unwrap_or(false),
+        start_after,
+        limit,
+        env.block.time.seconds(),
+        |id| InvoiceStore::load_invoice(deps.storage, &owner, id),
+        |invoice| invoice,
+    )?;
+
+    Ok(InvoiceListResponse { invoices, next })
+}
+
+/// Invoices left unaccepted past their expiry time are left out.
+#[allow(clippy::too_many_arguments)]
+pub fn filtered_contracts(
+    deps: Deps,
+    env: Env,
+    payer: Addr,
+    filter: Option<ListingFilter>,
+    sort_by: Option<SortBy>,
+    descending: Option<bool>,
+    start_after: Option<ListingCursor>,
+    limit: Option<u32>,
+) -> StdResult<ContractListResponse> {
+    let (contracts, next) = listing_page(
+        deps,
+        PAYER_ROLE,
+        &payer,
+        &filter.unwrap_or_default(),
+        sort_by.unwrap_or(SortBy::InvoiceId),
+        descending.unwrap_or(false),
+        start_after,
+        limit,
+        env.block.time.seconds(),
+        |id| ContractStore::load_contract(deps.storage, &payer, id),
+        |contract: &Contract| &contract.invoice,
+    )?;
+
+    Ok(ContractListResponse { contracts, next })
+}
+
+fn add_to_totals(totals: &mut Vec<TokenTotal>, token: &Token, amount: Uint128, funded: bool) {
+    let funded_amount = match funded {
+        true => amount,
+        false => Uint128::zero(),
+    };
+
+    match totals.iter_mut().find(|total| &total.token == token) {
+        Some(total) => {
+            total.amount += amount;
+            total.funded += funded_amount;
+        }
+        None => totals.push(TokenTotal {
+            token: token.clone(),
+            amount,
+            funded: funded_amount,
+        }),
+    }
+}
+
+/// Lists the payments of the active contracts of `payer` falling due
+/// between `from` and `to` (inclusive).
+pub fn payer_obligations(
+    deps: Deps,
+    payer: Addr,
+    from: u64,
+    to: u64,
+) -> StdResult<PayerObligationsResponse> {
+    let mut installments = vec![];
+    let mut totals = vec![];
+
+    for id in ListingIndex::ids(deps.storage, PAYER_ROLE, &payer, InvoiceStatus::Active)? {
+        let contract = ContractStore::load_contract(deps.storage, &payer, id);
+        let invoice = &contract.invoice;
+
+        // milestones fall due on their own date, or the payment time, until approved
+        if invoice.is_milestone_based() {
+            let mut needed = 0u128;
+            for (index, milestone) in invoice.milestones.iter().enumerate() {
+                if milestone.status == MilestoneStatus::Approved {
+                    continue;
+                }
+                needed += milestone.amount.u128();
+
+                let due_time = milestone.due_date.unwrap_or(invoice.payment_time);
+                if due_time < from || due_time > to {
+                    continue;
+                }
+
+                let funded = needed <= contract.account_balance;
+                add_to_totals(&mut totals, &invoice.token, milestone.amount, funded);
+                installments.push(Installment {
+                    invoice_id: id,
+                    index: index as u64,
+                    due_time,
+                    amount: milestone.amount,
+                    token: invoice.token.clone(),
+                    payee: invoice.receiver.clone(),
+                    funded,
+                });
+            }
+            continue;
+        }
+
+        let next = invoice.next_installment();
+        let count = invoice.installment_count();
+        let period = invoice.period_seconds();
+
+        // skip the payments due before the window
+        let mut index = next;
+        if period > 0 && from > invoice.installment_due_time(next) {
+            index = next + (from - invoice.installment_due_time(next)).div_ceil(period);
+        }
+
+        while index < count {
+            let due_time = invoice.installment_due_time(index);
+            if due_time > to {
+                break;
+            }
+
+            if due_time >= from {
+                // payments are drawn from the balance in schedule order
+                let needed = invoice.amount.u128() * u128::from(index - next + 1);
+                let funded = index < contract.funded_periods && needed <= contract.account_balance;
+
+                add_to_totals(&mut totals, &invoice.token, invoice.amount, funded);
+                installments.push(Installment {
+                    invoice_id: id,
+                    index,
+                    due_time,
+                    amount: invoice.amount,
+                    token: invoice.token.clone(),
+                    payee: invoice.receiver.clone(),
+                    funded,
+                });
+            }
+
+            index += 1;
+        }
+    }
+
+    installments.sort_by_key(|installment| (installment.due_time, installment.invoice_id));
+
+    Ok(PayerObligationsResponse {
+        installments,
+        totals,
+    })
+}
+
+/// Lists the withdrawals of `payee` in `token` made between `from` and `to`
+/// (inclusive), with the totals of the period.
+#[allow(clippy::too_many_arguments)]
+pub fn payee_statement(
+    deps: Deps,
+    env: Env,
+    payee: Addr,
+    from: u64,
+    to: u64,
+    token: Token,
+    page: u32,
+    auth: QueryAuth,
+) -> StdResult<PayeeStatementResponse> {
+    authenticate(deps, &env, &payee, &auth)?;
+
+    let (withdrawals, total) =
+        WithdrawalStore::page(deps.storage, &payee, from, to, page, STATEMENT_PAGE_SIZE)?;
+    let withdrawals: Vec<_> = withdrawals
+        .into_iter()
+        .filter(|withdrawal| withdrawal.token == token)
+        .collect();
+
+    let mut total_gross_amount = Uint128::zero();
+    let mut total_fee = Uint128::zero();
+    let mut total_withheld = Uint128::zero();
+    let mut total_net_amount = Uint128::zero();
+    for withdrawal in &withdrawals {
+        total_gross_amount += withdrawal.gross_amount;
+        total_fee += withdrawal.fee;
+        total_withheld += withdrawal.withheld;
+        total_net_amount += withdrawal.net_amount;
+    }
+
+    Ok(PayeeStatementResponse {
+        withdrawals,
+        total,
+        total_gross_amount,
+        total_fee,
+        total_withheld,
+        total_net_amount,
+    })
+}
+
+pub fn invoice_history(
+    deps: Deps,
+    env: Env,
+    id: u64,
+    address: Addr,
+    page: u32,
+    page_size: u32,
+    auth: QueryAuth,
+) -> StdResult<InvoiceHistoryResponse> {
+    authenticate(deps, &env, &address, &auth)?;
+
+    let parties = PartiesStore::load(deps.storage, id)?;
+
+    // only the payee and the payer can see the history
+    if !parties.is_party(&address) {
+        return Err(StdError::generic_err("You are not a party to this invoice"));
+    }
+
+    Ok(InvoiceHistoryResponse {
+        events: HistoryStore::paging_event_list(deps.storage, id, page, page_size)?,
+        total: HistoryStore::num_event(deps.storage, id),
+    })
+}
+
+/// What the next payment of a running contract needs from the payer treasury.
+fn treasury_draw(contract: &Contract) -> Uint128 {
+    if contract.contract_process != "started" || contract.invoice.remaining_time_of_payment == 0 {
+        return Uint128::zero();
+    }
+
+    let (escrow_shortfall, admin_fee) = contract.next_payment_shortfall();
+    escrow_shortfall + admin_fee
+}
+
+pub fn funding_status(
+    deps: Deps,
+    env: Env,
+    id: u64,
+    payer: Addr,
+) -> StdResult<FundingStatusResponse> {
+    let contract = ContractStore::load_contract(deps.storage, &payer, id);
+    let invoice = &contract.invoice;
+
+    let installments = invoice.installment_count();
+    let paid = invoice.next_installment();
+    let covered = contract.funded_periods.saturating_sub(paid);
+
+    let next_due_time = match paid < installments && contract.contract_accepted {
+        true => Some(invoice.installment_due_time(paid)),
+        false => None,
+    };
+
+    let treasury_balance = TreasuryStore::available(deps.storage, &payer, &invoice.denom()?);
+    let treasury = TreasuryStore::covering(deps.storage, &payer, invoice);
+
+    Ok(FundingStatusResponse {
+        installments,
+        paid,
+        funded: contract.funded_periods,
+        covered,
+        account_balance: contract.account_balance.into(),
+        next_due_time,
+        fully_funded: contract.funded_periods >= installments,
+        treasury_balance,
+        low_balance: next_due_time.is_some() && treasury_draw(&contract) > treasury_balance,
+        overdue_since: contract.overdue_since(treasury),
+        accrued_penalty: contract.penalty_at(env.block.time.seconds(), treasury),
+    })
+}
+
+pub fn treasury(deps: Deps, payer: Addr) -> StdResult<TreasuryResponse> {
+    let balance = TreasuryStore::load(deps.storage, &payer);
+
+    let mut next_payments = Uint128::zero();
+    for id in ListingIndex::ids(deps.storage, PAYER_ROLE, &payer, InvoiceStatus::Active)? {
+        let contract = ContractStore::load_contract(deps.storage, &payer, id);
+
+        // contracts funded in other tokens can not draw from the treasury
+        if contract.invoice.denom().ok().as_deref() == Some(TREASURY_DENOM) {
+            next_payments += treasury_draw(&contract);
+        }
+    }
+
+    Ok(TreasuryResponse {
+        balance,
+        next_payments,
+        low_balance: next_payments > balance,
+    })
+}
+
+pub fn dispute(
+    deps: Deps,
+    env: Env,
+    id: u64,
+    address: Addr,
+    auth: QueryAuth,
+) -> StdResult<DisputeResponse> {
+    authenticate(deps, &env, &address, &auth)?;
+
+    let parties = PartiesStore::load(deps.storage, id)?;
+    let payer = deps.api.addr_validate(parties.payer.as_str())?;
+    let contract = ContractStore::load_contract(deps.storage, &payer, id);
+    let arbiter = ArbiterStore::arbiter_of(deps.storage, &contract.invoice)?;
+
+    // only the parties and the arbiter can see the disputes
+    if !parties.is_party(&address) && arbiter.as_deref() != Some(address.as_str()) {
+        return Err(StdError::generic_err(
+            "You are not a party or the arbiter of this invoice",
+        ));
+    }
+
+    Ok(DisputeResponse {
+        arbiter,
+        open: contract.dispute,
+        history: DisputeStore::load_all(deps.storage, id)?,
+    })
+}
+
+pub fn organization(
+    deps: Deps,
+    env: Env,
+    id: u64,
+    address: Addr,
+    auth: QueryAuth,
+) -> StdResult<Organization> {
+    authenticate(deps, &env, &address, &auth)?;
+
+    let organization = OrganizationStore::load(deps.storage, id)?;
+
+    // only the organization can see its roster
+    if !organization.is_member(&address) {
+        return Err(StdError::generic_err(
+            "You are not a member of this organization",
+        ));
+    }
+
+    Ok(organization)
+}
+
+pub fn organization_contracts(
+    deps: Deps,
+    env: Env,
+    id: u64,
+    address: Addr,
+    page: u32,
+    page_size: u32,
+    auth: QueryAuth,
+) -> StdResult<OrganizationContractsResponse> {
+    authenticate(deps, &env, &address, &auth)?;
+
+    let organization = OrganizationStore::load(deps.storage, id)?;
+
+    // only the admins paying the organization invoices can list them
+    if !organization.is_admin(&address) {
+        return Err(StdError::generic_err(
+            "You are not an admin of this organization",
+        ));
+    }
+
+    let mut contracts = vec![];
+    for invoice_id in OrganizationStore::paging_invoice_ids(deps.storage, id, page, page_size)? {
+        let parties = PartiesStore::load(deps.storage, invoice_id)?;
+        let payer = deps.api.addr_validate(parties.payer.as_str())?;
+        contracts.push(ContractStore::load_contract(
+            deps.storage,
+            &payer,
+            invoice_id,
+        ));
+    }
+
+    Ok(OrganizationContractsResponse {
+        contracts,
+        total: OrganizationStore::num_invoices(deps.storage, id),
+    })
+}
+
+pub fn tags(deps: Deps, env: Env, id: u64, payer: Addr, auth: QueryAuth) -> StdResult<Vec<Tag>> {
+    authenticate(deps, &env, &payer, &auth)?;
+
+    let parties = PartiesStore::load(deps.storage, id)?;
+
+    if parties.payer != payer.as_str() {
+        return Err(StdError::generic_err(
+            "You are not the payer of this invoice",
+        ));
+    }
+
+    Ok(TagStore::load(deps.storage, id))
+}
+
+/// Sums what went in and out of escrow in a time window (inclusive) on the
+/// contracts of `payer` carrying `tag`, per token.
+pub fn spend_by_tag(
+    deps: Deps,
+    env: Env,
+    payer: Addr,
+    tag: Tag,
+    from: u64,
+    to: u64,
+    auth: QueryAuth,
+) -> StdResult<SpendByTagResponse> {
+    authenticate(deps, &env, &payer, &auth)?;
+
+    let totals = TagSpendStore::between(deps.storage, payer.as_str(), &tag, from, to)?;
+
+    Ok(SpendByTagResponse { totals })
+}
+
+/// Checks that `auth` proves ownership of `address`.
+fn authenticate(deps: Deps, env: &Env, address: &Addr, auth: &QueryAuth) -> StdResult<()> {
+    match auth {
+        QueryAuth::ViewingKey { key } => {
+            ViewingKey::check(deps.storage, address.as_str(), key.as_str())
+        }
+        QueryAuth::Permit { permit } => {
+            let signer = validate(
+                deps,
+                PREFIX_REVOKED_PERMITS,
+                permit,
+                env.contract.address.to_string(),
+                None,
+            )?;
+
+            if signer != address.as_str() {
+                return Err(StdError::generic_err(
+                    "Permit was not signed by this address",
+                ));
+            }
+
+            if !permit.check_permission(&TokenPermissions::History)
+                && !permit.check_permission(&TokenPermissions::Owner)
+            {
+                return Err(StdError::generic_err(
+                    "Permit does not grant access to the history",
+                ));
+            }
+
+            Ok(())
+        }
+    }
+}
+
+pub fn payroll_report(
+    deps: Deps,
+    env: Env,
+    payer: Addr,
+    from: u64,
+    to: u64,
+    page: u32,
+    auth: QueryAuth,
+) -> StdResult<PayrollReportResponse> {
+    authenticate(deps, &env, &payer, &auth)?;
+
+    let (rows, total) = PayrollStore::page(
+        deps.storage,
+        payer.as_str(),
+        from,
+        to,
+        page,
+        PAYROLL_PAGE_SIZE,
+    )?;
+
+    Ok(PayrollReportResponse { rows, total })
+}
+
+pub fn memo(
+    deps: Deps,
+    env: Env,
+    id: u64,
+    address: Addr,
+    auth: QueryAuth,
+) -> StdResult<MemoResponse> {
+    authenticate(deps, &env, &address, &auth)?;
+
+    let parties = PartiesStore::load(deps.storage, id)?;
+
+    // only the payee and the payer can read the memo
+    if !parties.is_party(&address) {
+        return Err(StdError::generic_err("You are not a party to this invoice"));
+    }
+
+    Ok(MemoResponse {
+        memo: MemoStore::load(deps.storage, id),
+        line_items: LineItemStore::load(deps.storage, id),
+    })
+}
@@ -1,13 +1,37 @@
 use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, DepsMut, StdResult, Storage, Uint128};
+use cosmwasm_std::{to_vec, Addr, Decimal, DepsMut, Env, StdError, StdResult, Storage, Uint128};
 use secret_toolkit::serialization::Json;
-use secret_toolkit::storage::{Item, Keymap};
+use secret_toolkit::storage::{AppendStore, Item, Keymap};
 use secret_toolkit::utils::types::Token;
 
 pub const PREFIX_INVOICE: &[u8] = b"invoice";
 pub const PREFIX_CONTRACT: &[u8] = b"contract";
+pub const PREFIX_SORTED_META: &[u8] = b"sorted_meta";
+pub const PREFIX_SORTED_CHUNK: &[u8] = b"sorted_chunk";
+pub const PREFIX_WITHDRAWAL: &[u8] = b"withdrawal";
+pub const PREFIX_PAYROLL: &[u8] = b"payroll";
+pub const PREFIX_INVOICE_PARTIES: &[u8] = b"invoice_parties";
+pub const PREFIX_INVOICE_EVENT: &[u8] = b"invoice_event";
+pub const PREFIX_TREASURY: &[u8] = b"treasury";
+pub const PREFIX_DISPUTE: &[u8] = b"dispute";
+pub const PREFIX_EXPIRY: &[u8] = b"expiry";
+pub const PREFIX_OPERATOR: &[u8] = b"operator";
+pub const PREFIX_ORGANIZATION: &[u8] = b"organization";
+pub const PREFIX_ORGANIZATION_INVOICE: &[u8] = b"organization_invoice";
+pub const PREFIX_TAG: &[u8] = b"tag";
+pub const PREFIX_TAG_SPEND: &[u8] = b"tag_spend";
+pub const PREFIX_MEMO: &[u8] = b"memo";
+pub const PREFIX_LINE_ITEMS: &[u8] = b"line_items";
+pub const PREFIX_REVOKED_PERMITS: &str = "revoked_permits";
+
+pub const PAYEE_ROLE: &[u8] = b"payee";
+pub const PAYER_ROLE: &[u8] = b"payer";
+
+/// Denomination the payer treasury is held in.
+pub const TREASURY_DENOM: &str = "uscrt";
 
 #[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
 pub struct Invoice {
@@ -27,6 +51,288 @@ pub struct Invoice {
     pub critical_time: u64,
     pub payment_condition: String,
     pub token: Token,
+    #[serde(default)]
+    pub late_payment: Option<LatePaymentTerms>,
+    #[serde(default)]
+    pub early_payment: Option<EarlyPaymentTerms>,
+    /// time the invoice was submitted at
+    #[serde(default)]
+    pub submitted_at: u64,
+    /// discount granted on each payment for paying early
+    #[serde(default)]
+    pub discount: Uint128,
+    /// milestones paying out the invoice amount, empty for time based invoices
+    #[serde(default)]
+    pub milestones: Vec<Milestone>,
+    /// days the payer has to review a submitted milestone before the payee can approve it
+    #[serde(default)]
+    pub review_period_days: u64,
+    /// arbiter settling disputes on this invoice instead of the global one
+    #[serde(default)]
+    pub arbiter: Option<String>,
+    /// cancellation terms proposed by the payee and agreed by the payer on acceptance
+    #[serde(default)]
+    pub cancellation_policy: Option<CancellationPolicy>,
+    /// time of the last payment of a contract terminated with notice
+    #[serde(default)]
+    pub final_payout_time: Option<u64>,
+    /// time after which the invoice can no longer be accepted
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    /// address the payee is paid at instead of `receiver`
+    #[serde(default)]
+    pub payout_address: Option<String>,
+    /// shares the payee payments are split in, overriding `payout_address`
+    #[serde(default)]
+    pub payout_split: Vec<PayoutShare>,
+    /// tokens the payee accepts funding in besides `token`
+    #[serde(default)]
+    pub funding_tokens: Vec<FundingToken>,
+    /// price of the invoice before it was converted to the token it was funded in
+    #[serde(default)]
+    pub pricing: Option<Pricing>,
+    /// organization billed, any of its admins can accept the invoice
+    #[serde(default)]
+    pub organization: Option<u64>,
+    /// income tax the payer withholds from each payment
+    #[serde(default)]
+    pub withholding: Option<Withholding>,
+}
+
+/// Share of the payee payments withheld and paid to a tax authority instead.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Withholding {
+    pub basis_points: u64,
+    pub authority: String,
+    /// set once the payee agrees to it, nothing is withheld before
+    #[serde(default)]
+    pub agreed: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct LineItem {
+    pub description: String,
+    pub quantity: u64,
+    pub unit_price: Uint128,
+    /// tax charged on the line
+    pub tax: Uint128,
+}
+
+impl LineItem {
+    pub fn total(&self) -> Uint128 {
+        self.unit_price * Uint128::from(self.quantity) + self.tax
+    }
+}
+
+/// Token an invoice can be funded in, at a rate set by the payer and agreed by the payee.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct FundingToken {
+    pub token: Token,
+    /// units of `token` paid per unit of the invoice token
+    pub rate: Option<Decimal>,
+    pub agreed: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Pricing {
+    pub token: Token,
+    pub amount: Uint128,
+    pub rate: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct PayoutShare {
+    pub address: String,
+    pub basis_points: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct CancellationPolicy {
+    /// payments falling due within this many days of the cancellation are still paid
+    pub notice_days: u64,
+    /// share of the payments left after the notice period paid to the payee, in basis points
+    pub kill_fee_basis_points: u64,
+    /// whether payments already due but not withdrawn yet are still paid
+    pub protect_earned: bool,
+}
+
+/// Penalty charged per day a payment stays unfunded after its grace period.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LateFee {
+    /// basis points of the payment amount per day
+    Percentage { basis_points: u64 },
+    /// fixed amount per day
+    Flat { amount: Uint128 },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct LatePaymentTerms {
+    pub grace_period_days: u64,
+    pub late_fee: LateFee,
+}
+
+/// Discount granted when the invoice is funded within `deadline_days` of its submission.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct EarlyPaymentTerms {
+    pub basis_points: u64,
+    pub deadline_days: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MilestoneStatus {
+    Pending,
+    Submitted,
+    Approved,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct MilestoneTerms {
+    pub description: String,
+    pub amount: Uint128,
+    pub due_date: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct MilestonePlan {
+    pub milestones: Vec<MilestoneTerms>,
+    pub review_period_days: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Milestone {
+    pub description: String,
+    pub amount: Uint128,
+    pub due_date: Option<u64>,
+    pub status: MilestoneStatus,
+    pub proof: Option<String>,
+    pub submitted_at: Option<u64>,
+}
+
+impl Invoice {
+    /// Whether the invoice was left unaccepted past its expiry time.
+    pub fn is_expired(&self, now: u64) -> bool {
+        match self.expires_at {
+            Some(expires_at) => self.status == "not started" && now >= expires_at,
+            None => false,
+        }
+    }
+
+    pub fn is_milestone_based(&self) -> bool {
+        !self.milestones.is_empty()
+    }
+
+    /// Denomination the payments are settled in. Only native tokens can be settled.
+    pub fn denom(&self) -> StdResult<String> {
+        match &self.token {
+            Token::Native(denom) => Ok(denom.clone()),
+            Token::Snip20(_) => Err(StdError::generic_err("SNIP-20 invoices can not be settled")),
+        }
+    }
+
+    /// Tax withheld from `net`, the share of a payment left to the payee after the charges.
+    pub fn withheld_from(&self, net: Uint128) -> Uint128 {
+        match &self.withholding {
+            Some(withholding) if withholding.agreed => {
+                net.multiply_ratio(withholding.basis_points, 10000u64)
+            }
+            _ => Uint128::zero(),
+        }
+    }
+
+    /// Agreed rate of `token` if the invoice can be funded in it.
+    pub fn funding_rate(&self, token: &Token) -> Option<Decimal> {
+        self.funding_tokens
+            .iter()
+            .find(|funding| funding.token == *token && funding.agreed)
+            .and_then(|funding| funding.rate)
+    }
+
+    /// Reprices the invoice in `token` at `rate`, keeping the original price in `pricing`.
+    pub fn convert(&mut self, token: Token, rate: Decimal) {
+        self.pricing = Some(Pricing {
+            token: self.token.clone(),
+            amount: self.amount,
+            rate,
+        });
+        self.token = token;
+        self.amount = self.amount * rate;
+        self.admin_charges = self.admin_charges * rate;
+        self.customer_charges = self.customer_charges * rate;
+
+        if let Some(LatePaymentTerms { late_fee: LateFee::Flat { amount }, .. }) =
+            &mut self.late_payment
+        {
+            *amount = *amount * rate;
+        }
+
+        // the milestones keep adding up to the amount
+        if self.is_milestone_based() {
+            for milestone in self.milestones.iter_mut() {
+                milestone.amount = milestone.amount * rate;
+            }
+            self.amount = self.milestones.iter().map(|milestone| milestone.amount).sum();
+        }
+    }
+
+    /// Share of the payee charges taken on milestone `index`, split by amount
+    /// so that the shares add up to the charges exactly.
+    pub fn milestone_fee(&self, index: usize) -> Uint128 {
+        let total: Uint128 = self.milestones.iter().map(|milestone| milestone.amount).sum();
+        let before: Uint128 = self.milestones[..index]
+            .iter()
+            .map(|milestone| milestone.amount)
+            .sum();
+        let through = before + self.milestones[index].amount;
+
+        self.customer_charges.multiply_ratio(through, total)
+            - self.customer_charges.multiply_ratio(before, total)
+    }
+
+    /// Payment amount after the early payment discount, when it still applies at `now`.
+    pub fn discounted_amount(&self, now: u64) -> Option<Uint128> {
+        let terms = self.early_payment.as_ref()?;
+
+        if now > self.submitted_at + terms.deadline_days * 86400 {
+            return None;
+        }
+
+        Some(self.amount - self.amount.multiply_ratio(terms.basis_points, 10000u64))
+    }
+
+    /// Number of payments the invoice is made of.
+    pub fn installment_count(&self) -> u64 {
+        match self.recurrent {
+            Some(true) => self.recurrent_times,
+            _ => 1,
+        }
+    }
+
+    /// Seconds between two payments of a recurrent invoice.
+    pub fn period_seconds(&self) -> u64 {
+        self.days * 86400
+    }
+
+    /// Index of the next payment to be withdrawn.
+    pub fn next_installment(&self) -> u64 {
+        self.installment_count()
+            .saturating_sub(self.remaining_time_of_payment)
+    }
+
+    /// Periods still paid after a termination with notice: the notice days of the
+    /// cancellation policy rounded up to whole periods, or else one period.
+    pub fn notice_periods(&self) -> u64 {
+        match (&self.cancellation_policy, self.days) {
+            (Some(policy), days) if days > 0 => policy.notice_days.div_ceil(days).max(1),
+            _ => 1,
+        }
+    }
+
+    /// Time from which the payment at `index` can be withdrawn.
+    pub fn installment_due_time(&self, index: u64) -> u64 {
+        self.payment_time + index * self.period_seconds()
+    }
 }
 
 #[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
@@ -36,6 +342,216 @@ pub struct Contract {
     pub contract_process: String,
     pub invoice: Invoice,
     pub contract_accepted: bool,
+    /// number of payments the payer has deposited for so far
+    #[serde(default = "legacy_funded_periods")]
+    pub funded_periods: u64,
+    /// late penalty owed to the payee, paid out of the next top up or refund
+    #[serde(default)]
+    pub accrued_penalty: Uint128,
+    /// time up to which the late penalty has been accrued
+    #[serde(default)]
+    pub penalty_accrued_until: u64,
+    /// dispute waiting for the arbiter, freezing withdrawals and refunds
+    #[serde(default)]
+    pub dispute: Option<Dispute>,
+    /// amendment waiting for the counterparty to accept it
+    #[serde(default)]
+    pub pending_amendment: Option<Amendment>,
+    /// accepted amendments, oldest first
+    #[serde(default)]
+    pub amendments: Vec<Amendment>,
+}
+
+/// Change of the payments left in a running contract.
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
+pub struct Amendment {
+    pub proposed_by: String,
+    pub new_amount: Uint128,
+    /// days between payments
+    pub new_period: u64,
+    /// number of payments left
+    pub new_count: u64,
+    pub proposed_at: u64,
+    pub accepted_at: Option<u64>,
+}
+
+/// Lifecycle of an invoice as shown to listing queries, derived from the
+/// free-form `status` and `remaining_time_of_payment` fields.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum InvoiceStatus {
+    Pending,
+    Active,
+    Cancelled,
+    Completed,
+}
+
+impl InvoiceStatus {
+    pub const ALL: [InvoiceStatus; 4] = [
+        InvoiceStatus::Pending,
+        InvoiceStatus::Active,
+        InvoiceStatus::Cancelled,
+        InvoiceStatus::Completed,
+    ];
+
+    pub fn of(invoice: &Invoice) -> Self {
+        match invoice.status.as_str() {
+            "not started" => InvoiceStatus::Pending,
+            "stop" => InvoiceStatus::Cancelled,
+            "done" => InvoiceStatus::Completed,
+            _ if invoice.remaining_time_of_payment == 0 => InvoiceStatus::Completed,
+            _ => InvoiceStatus::Active,
+        }
+    }
+
+    fn key(&self) -> &'static [u8] {
+        match self {
+            InvoiceStatus::Pending => b"pending",
+            InvoiceStatus::Active => b"active",
+            InvoiceStatus::Cancelled => b"cancelled",
+            InvoiceStatus::Completed => b"completed",
+        }
+    }
+}
+
+/// Marks contracts stored before `funded_periods` existed.
+const LEGACY_FUNDED_PERIODS: u64 = u64::MAX;
+
+fn legacy_funded_periods() -> u64 {
+    LEGACY_FUNDED_PERIODS
+}
+
+impl Contract {
+    /// Fills in the fields contracts stored by earlier versions lack.
+    pub fn upgraded(mut self) -> Self {
+        // accepted contracts were funded in full upfront
+        if self.funded_periods == LEGACY_FUNDED_PERIODS {
+            self.funded_periods = match self.contract_accepted {
+                true => self.invoice.installment_count(),
+                false => 0,
+            };
+        }
+
+        self
+    }
+
+    /// Whether the deposits made so far pay for the next payment.
+    pub fn next_payment_funded(&self) -> bool {
+        self.invoice.next_installment() < self.funded_periods
+    }
+
+    /// Escrow and admin charge still missing to pay out the next payment.
+    pub fn next_payment_shortfall(&self) -> (Uint128, Uint128) {
+        let escrow = self
+            .invoice
+            .amount
+            .saturating_sub(Uint128::from(self.account_balance));
+
+        let admin_fee = match self.next_payment_funded() {
+            true => Uint128::zero(),
+            false => self.invoice.admin_charges,
+        };
+
+        (escrow, admin_fee)
+    }
+
+    /// Time from which the next payment is late, when neither the balance nor the
+    /// payer `treasury` available to draw from covers it.
+    pub fn overdue_since(&self, treasury: Uint128) -> Option<u64> {
+        let terms = self.invoice.late_payment.as_ref()?;
+
+        if self.contract_process != "started" || self.invoice.remaining_time_of_payment == 0 {
+            return None;
+        }
+
+        let (escrow_shortfall, admin_fee) = self.next_payment_shortfall();
+        if escrow_shortfall + admin_fee <= treasury {
+            return None;
+        }
+
+        let due_time = self
+            .invoice
+            .installment_due_time(self.invoice.next_installment());
+
+        Some(due_time + terms.grace_period_days * 86400)
+    }
+
+    /// Start and number of whole days of penalty not accrued yet at `now`.
+    fn unaccrued_penalty_days(&self, now: u64, treasury: Uint128) -> (u64, u64) {
+        match self.overdue_since(treasury) {
+            Some(since) => {
+                let start = since.max(self.penalty_accrued_until);
+                (start, now.saturating_sub(start) / 86400)
+            }
+            None => (now, 0),
+        }
+    }
+
+    fn daily_penalty(&self) -> Uint128 {
+        match &self.invoice.late_payment {
+            Some(LatePaymentTerms {
+                late_fee: LateFee::Percentage { basis_points },
+                ..
+            }) => self.invoice.amount.multiply_ratio(*basis_points, 10000u64),
+            Some(LatePaymentTerms {
+                late_fee: LateFee::Flat { amount },
+                ..
+            }) => *amount,
+            None => Uint128::zero(),
+        }
+    }
+
+    /// Late penalty owed at `now`, including the days not accrued yet.
+    pub fn penalty_at(&self, now: u64, treasury: Uint128) -> Uint128 {
+        let (_, days) = self.unaccrued_penalty_days(now, treasury);
+
+        self.accrued_penalty + self.daily_penalty() * Uint128::from(days)
+    }
+
+    /// What the payee keeps out of the balance when the payer cancels at `now`
+    /// under the cancellation policy of the invoice.
+    pub fn cancellation_share(&self, policy: &CancellationPolicy, now: u64) -> Uint128 {
+        let invoice = &self.invoice;
+        let balance = Uint128::from(self.account_balance);
+
+        // milestones are only owed once approved, the kill fee applies to the rest
+        if invoice.is_milestone_based() {
+            return balance
+                .multiply_ratio(policy.kill_fee_basis_points, 10000u64)
+                .min(balance);
+        }
+
+        let notice_end = now + policy.notice_days * 86400;
+        let mut owed = 0u64;
+        let mut left = 0u64;
+        for index in invoice.next_installment()..invoice.installment_count() {
+            let due_time = invoice.installment_due_time(index);
+
+            if due_time <= notice_end && (policy.protect_earned || due_time > now) {
+                owed += 1;
+            } else if due_time > notice_end {
+                left += 1;
+            }
+        }
+
+        let kill_fee = (invoice.amount * Uint128::from(left))
+            .multiply_ratio(policy.kill_fee_basis_points, 10000u64);
+
+        (invoice.amount * Uint128::from(owed) + kill_fee).min(balance)
+    }
+
+    /// Adds the whole days of penalty elapsed until `now` to the accrued penalty.
+    /// Time during which the payment is covered is never charged later on.
+    pub fn accrue_penalty(&mut self, now: u64, treasury: Uint128) {
+        let (start, days) = self.unaccrued_penalty_days(now, treasury);
+
+        if days > 0 {
+            self.accrued_penalty += self.daily_penalty() * Uint128::from(days);
+            self.penalty_accrued_until = start + days * 86400;
+        } else if self.overdue_since(treasury).is_none() {
+            self.penalty_accrued_until = self.penalty_accrued_until.max(now);
+        }
+    }
 }
 
 const INVOICE_ID: Item<u64> = Item::new(b"invoice_id");
@@ -50,6 +566,41 @@ pub fn get_next_invoice_id(storage: &mut dyn Storage) -> StdResult<u64> {
     Ok(new_id)
 }
 
+const ORGANIZATION_ID: Item<u64> = Item::new(b"organization_id");
+
+pub fn get_next_organization_id(storage: &mut dyn Storage) -> StdResult<u64> {
+    let new_id = match ORGANIZATION_ID.may_load(storage)? {
+        Some(id) => id + 1,
+        None => 1,
+    };
+    ORGANIZATION_ID.save(storage, &new_id)?;
+
+    Ok(new_id)
+}
+
+// arbiter settling the disputes of invoices that do not name their own
+const ARBITER: Item<String> = Item::new(b"arbiter");
+
+pub struct ArbiterStore {}
+
+impl ArbiterStore {
+    pub fn save(storage: &mut dyn Storage, arbiter: &Addr) -> StdResult<()> {
+        ARBITER.save(storage, &arbiter.to_string())
+    }
+
+    pub fn load(storage: &dyn Storage) -> StdResult<Option<String>> {
+        ARBITER.may_load(storage)
+    }
+
+    /// Arbiter of `invoice`: its own one, or else the global one.
+    pub fn arbiter_of(storage: &dyn Storage, invoice: &Invoice) -> StdResult<Option<String>> {
+        match &invoice.arbiter {
+            Some(arbiter) => Ok(Some(arbiter.clone())),
+            None => Self::load(storage),
+        }
+    }
+}
+
 const ADMIN_WALLET_ID: &[u8] = b"user_wallet";
 
 pub struct  AdminStore();
@@ -76,6 +627,334 @@ impl AdminStore {
     
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortBy {
+    InvoiceId,
+    DueDate,
+    Amount,
+}
+
+impl SortBy {
+    pub const ALL: [SortBy; 3] = [SortBy::InvoiceId, SortBy::DueDate, SortBy::Amount];
+
+    pub fn key_of(&self, invoice: &Invoice) -> u128 {
+        match self {
+            SortBy::InvoiceId => u128::from(invoice.invoice_id),
+            SortBy::DueDate => u128::from(invoice.payment_time),
+            SortBy::Amount => invoice.amount.u128(),
+        }
+    }
+
+    fn key(&self) -> &'static [u8] {
+        match self {
+            SortBy::InvoiceId => b"invoice_id",
+            SortBy::DueDate => b"due_date",
+            SortBy::Amount => b"amount",
+        }
+    }
+}
+
+/// Position of an invoice in a sorted index.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IndexEntry {
+    pub key: u128,
+    pub id: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+struct ChunkHead {
+    first: IndexEntry,
+    chunk: u32,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct SortedMeta {
+    chunks: Vec<ChunkHead>,
+    next_chunk: u32,
+}
+
+const SORTED_META: Item<SortedMeta, Json> = Item::new(PREFIX_SORTED_META);
+const SORTED_CHUNK: Item<Vec<IndexEntry>, Json> = Item::new(PREFIX_SORTED_CHUNK);
+
+const CHUNK_SIZE: usize = 32;
+
+/// Entries kept in key order in chunks of at most `CHUNK_SIZE`, so a page
+/// only reads the chunks it returns.
+pub struct SortedIndex {
+    namespace: Vec<u8>,
+}
+
+impl SortedIndex {
+    pub fn new(namespace: Vec<u8>) -> Self {
+        SortedIndex { namespace }
+    }
+
+    fn meta(&self) -> Item<'static, SortedMeta, Json> {
+        SORTED_META.add_suffix(&self.namespace)
+    }
+
+    fn chunk(&self, chunk: u32) -> Item<'static, Vec<IndexEntry>, Json> {
+        SORTED_CHUNK
+            .add_suffix(&self.namespace)
+            .add_suffix(&chunk.to_be_bytes())
+    }
+
+    /// Position of the chunk that holds or would hold `entry`.
+    fn position(meta: &SortedMeta, entry: &IndexEntry) -> usize {
+        meta.chunks
+            .partition_point(|head| head.first <= *entry)
+            .saturating_sub(1)
+    }
+
+    pub fn insert(&self, store: &mut dyn Storage, entry: IndexEntry) -> StdResult<()> {
+        let mut meta = self.meta().may_load(store)?.unwrap_or_default();
+        if meta.chunks.is_empty() {
+            meta.chunks.push(ChunkHead {
+                first: entry,
+                chunk: meta.next_chunk,
+            });
+            meta.next_chunk += 1;
+        }
+
+        let position = Self::position(&meta, &entry);
+        let head = meta.chunks[position];
+        let mut entries = self.chunk(head.chunk).may_load(store)?.unwrap_or_default();
+        match entries.binary_search(&entry) {
+            Ok(_) => return Ok(()),
+            Err(index) => entries.insert(index, entry),
+        }
+        meta.chunks[position].first = entries[0];
+
+        // split a full chunk in two halves
+        if entries.len() > CHUNK_SIZE {
+            let upper = entries.split_off(entries.len() / 2);
+            let chunk = meta.next_chunk;
+            meta.next_chunk += 1;
+            meta.chunks.insert(
+                position + 1,
+                ChunkHead {
+                    first: upper[0],
+                    chunk,
+                },
+            );
+            self.chunk(chunk).save(store, &upper)?;
+        }
+
+        self.chunk(head.chunk).save(store, &entries)?;
+        self.meta().save(store, &meta)
+    }
+
+    pub fn remove(&self, store: &mut dyn Storage, entry: IndexEntry) -> StdResult<()> {
+        let mut meta = match self.meta().may_load(store)? {
+            Some(meta) if !meta.chunks.is_empty() => meta,
+            _ => return Ok(()),
+        };
+
+        let position = Self::position(&meta, &entry);
+        let head = meta.chunks[position];
+        let mut entries = self.chunk(head.chunk).may_load(store)?.unwrap_or_default();
+        match entries.binary_search(&entry) {
+            Ok(index) => entries.remove(index),
+            Err(_) => return Ok(()),
+        };
+
+        if entries.is_empty() {
+            meta.chunks.remove(position);
+            self.chunk(head.chunk).remove(store);
+        } else {
+            meta.chunks[position].first = entries[0];
+            self.chunk(head.chunk).save(store, &entries)?;
+        }
+
+        self.meta().save(store, &meta)
+    }
+
+    /// Calls `visit` on the entries after `start_after` in key order, one
+    /// chunk at a time, until it returns false.
+    pub fn visit<F>(
+        &self,
+        store: &dyn Storage,
+        start_after: Option<IndexEntry>,
+        descending: bool,
+        mut visit: F,
+    ) -> StdResult<()>
+    where
+        F: FnMut(IndexEntry) -> StdResult<bool>,
+    {
+        let meta = self.meta().may_load(store)?.unwrap_or_default();
+        if meta.chunks.is_empty() {
+            return Ok(());
+        }
+
+        let start = match (start_after, descending) {
+            (Some(entry), _) => Self::position(&meta, &entry),
+            (None, false) => 0,
+            (None, true) => meta.chunks.len() - 1,
+        };
+        let positions: Vec<usize> = match descending {
+            false => (start..meta.chunks.len()).collect(),
+            true => (0..=start).rev().collect(),
+        };
+
+        for position in positions {
+            let mut entries = self
+                .chunk(meta.chunks[position].chunk)
+                .may_load(store)?
+                .unwrap_or_default();
+            if descending {
+                entries.reverse();
+            }
+
+            for entry in entries {
+                let after = match (start_after, descending) {
+                    (Some(start_after), false) => entry > start_after,
+                    (Some(start_after), true) => entry < start_after,
+                    (None, _) => true,
+                };
+                if after && !visit(entry)? {
+                    return Ok(());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Subset of the invoices of an address a listing index covers.
+pub enum ListingScope<'a> {
+    All,
+    Status(InvoiceStatus),
+    Token(&'a Token),
+    Counterparty(&'a str),
+}
+
+impl ListingScope<'_> {
+    fn key(&self) -> StdResult<Vec<u8>> {
+        Ok(match self {
+            ListingScope::All => b"all".to_vec(),
+            ListingScope::Status(status) => [b"status/".as_slice(), status.key()].concat(),
+            ListingScope::Token(token) => [b"token/".to_vec(), to_vec(token)?].concat(),
+            ListingScope::Counterparty(address) => {
+                [b"counterparty/".as_slice(), address.as_bytes()].concat()
+            }
+        })
+    }
+}
+
+/// Sorted indexes of the invoices per role and address, one per scope and
+/// sort order, so filtered listings page through storage keys.
+pub struct ListingIndex {}
+
+impl ListingIndex {
+    pub fn index(
+        role: &[u8],
+        owner: &Addr,
+        scope: &ListingScope,
+        sort_by: SortBy,
+    ) -> StdResult<SortedIndex> {
+        let mut namespace = vec![];
+        for part in [role, owner.as_bytes(), &scope.key()?, sort_by.key()] {
+            namespace.extend_from_slice(&(part.len() as u16).to_be_bytes());
+            namespace.extend_from_slice(part);
+        }
+
+        Ok(SortedIndex::new(namespace))
+    }
+
+    fn entries(
+        role: &[u8],
+        owner: &Addr,
+        invoice: &Invoice,
+    ) -> StdResult<Vec<(SortedIndex, IndexEntry)>> {
+        let counterparty = match role == PAYEE_ROLE {
+            true => invoice.payer.as_str(),
+            false => invoice.receiver.as_str(),
+        };
+        let scopes = [
+            ListingScope::All,
+            ListingScope::Status(InvoiceStatus::of(invoice)),
+            ListingScope::Token(&invoice.token),
+            ListingScope::Counterparty(counterparty),
+        ];
+
+        let mut entries = vec![];
+        for scope in scopes.iter() {
+            for sort_by in SortBy::ALL {
+                let entry = IndexEntry {
+                    key: sort_by.key_of(invoice),
+                    id: invoice.invoice_id,
+                };
+                entries.push((Self::index(role, owner, scope, sort_by)?, entry));
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Ids of the invoices of `owner` in `status`.
+    pub fn ids(
+        store: &dyn Storage,
+        role: &[u8],
+        owner: &Addr,
+        status: InvoiceStatus,
+    ) -> StdResult<Vec<u64>> {
+        let index = Self::index(role, owner, &ListingScope::Status(status), SortBy::InvoiceId)?;
+
+        let mut ids = vec![];
+        index.visit(store, None, false, |entry| {
+            ids.push(entry.id);
+            Ok(true)
+        })?;
+
+        Ok(ids)
+    }
+
+    /// Replaces the entries of `previous` with those of `current`. Every entry of `current`
+    /// is inserted, so invoices stored before the index existed are indexed when next saved.
+    fn update(
+        store: &mut dyn Storage,
+        role: &[u8],
+        owner: &Addr,
+        previous: Option<&Invoice>,
+        current: &Invoice,
+    ) -> StdResult<()> {
+        let current_entries = Self::entries(role, owner, current)?;
+
+        if let Some(previous) = previous {
+            for (index, entry) in Self::entries(role, owner, previous)? {
+                let kept = current_entries.iter().any(|(other, other_entry)| {
+                    other.namespace == index.namespace && *other_entry == entry
+                });
+                if !kept {
+                    index.remove(store, entry)?;
+                }
+            }
+        }
+
+        // inserting an entry already in the index leaves it as is
+        for (index, entry) in current_entries {
+            index.insert(store, entry)?;
+        }
+
+        Ok(())
+    }
+
+    fn remove(
+        store: &mut dyn Storage,
+        role: &[u8],
+        owner: &Addr,
+        invoice: &Invoice,
+    ) -> StdResult<()> {
+        for (index, entry) in Self::entries(role, owner, invoice)? {
+            index.remove(store, entry)?;
+        }
+
+        Ok(())
+    }
+}
+
 pub static INVOICE: Keymap<u64, Invoice, Json> = Keymap::new(PREFIX_INVOICE);
 
 pub struct InvoiceStore {}
@@ -94,9 +973,21 @@ impl InvoiceStore {
         id: u64,
         invoice: &Invoice,
     ) -> StdResult<()> {
-        INVOICE
-            .add_suffix(owner.as_bytes())
-            .insert(store, &id, invoice)
+        let invoices = INVOICE.add_suffix(owner.as_bytes());
+        let previous = invoices.get(store, &id);
+        ListingIndex::update(store, PAYEE_ROLE, owner, previous.as_ref(), invoice)?;
+
+        invoices.insert(store, &id, invoice)
+    }
+
+    pub fn remove(store: &mut dyn Storage, owner: &Addr, id: u64) -> StdResult<()> {
+        let invoices = INVOICE.add_suffix(owner.as_bytes());
+        if let Some(invoice) = invoices.get(store, &id) {
+            ListingIndex::remove(store, PAYEE_ROLE, owner, &invoice)?;
+            invoices.remove(store, &id)?;
+        }
+
+        Ok(())
     }
 
     pub fn paging_invoice_list(
@@ -129,9 +1020,27 @@ impl ContractStore {
         id: u64,
         contract: &Contract,
     ) -> StdResult<()> {
-        CONTRACT
-            .add_suffix(payer.as_bytes())
-            .insert(store, &id, contract)
+        let contracts = CONTRACT.add_suffix(payer.as_bytes());
+        let previous = contracts.get(store, &id).map(|contract| contract.invoice);
+        ListingIndex::update(
+            store,
+            PAYER_ROLE,
+            payer,
+            previous.as_ref(),
+            &contract.invoice,
+        )?;
+
+        contracts.insert(store, &id, contract)
+    }
+
+    pub fn remove(store: &mut dyn Storage, payer: &Addr, id: u64) -> StdResult<()> {
+        let contracts = CONTRACT.add_suffix(payer.as_bytes());
+        if let Some(contract) = contracts.get(store, &id) {
+            ListingIndex::remove(store, PAYER_ROLE, payer, &contract.invoice)?;
+            contracts.remove(store, &id)?;
+        }
+
+        Ok(())
     }
 
     pub fn load_contract(store: &dyn Storage, payer: &Addr, id: u64) -> Contract {
@@ -139,6 +1048,7 @@ impl ContractStore {
             .add_suffix(payer.as_bytes())
             .get(store, &id.clone())
             .unwrap()
+            .upgraded()
     }
 
     pub fn paging_contract_list(
@@ -147,9 +1057,12 @@ impl ContractStore {
         page: u32,
         page_size: u32,
     ) -> StdResult<Vec<(u64, Contract)>> {
-        CONTRACT
+        Ok(CONTRACT
             .add_suffix(payer.as_bytes())
-            .paging(store, page, page_size)
+            .paging(store, page, page_size)?
+            .into_iter()
+            .map(|(id, contract)| (id, contract.upgraded()))
+            .collect())
     }
 
     pub fn num_contract(store: &dyn Storage, payer: &Addr) -> u32 {
@@ -159,3 +1072,749 @@ impl ContractStore {
             .unwrap_or(0)
     }
 }
+
+/// A payment withdrawn by a payee, kept for their earnings statement.
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
+pub struct Withdrawal {
+    pub invoice_id: u64,
+    pub time: u64,
+    pub gross_amount: Uint128,
+    pub fee: Uint128,
+    /// tax withheld and paid to the tax authority
+    #[serde(default)]
+    pub withheld: Uint128,
+    pub net_amount: Uint128,
+    pub token: Token,
+}
+
+pub static WITHDRAWAL: AppendStore<Withdrawal, Json> = AppendStore::new(PREFIX_WITHDRAWAL);
+
+pub struct WithdrawalStore {}
+
+impl WithdrawalStore {
+    pub fn push(store: &mut dyn Storage, payee: &Addr, withdrawal: &Withdrawal) -> StdResult<()> {
+        WITHDRAWAL
+            .add_suffix(payee.as_bytes())
+            .push(store, withdrawal)
+    }
+
+    /// Page `page` of the withdrawals of `payee` between `from` and `to`, and the number
+    /// of withdrawals in that window.
+    pub fn page(
+        store: &dyn Storage,
+        payee: &Addr,
+        from: u64,
+        to: u64,
+        page: u32,
+        page_size: u32,
+    ) -> StdResult<(Vec<Withdrawal>, u32)> {
+        let withdrawals = WITHDRAWAL.add_suffix(payee.as_bytes());
+
+        page_by_time(
+            store,
+            &withdrawals,
+            from,
+            to,
+            page,
+            page_size,
+            |withdrawal| withdrawal.time,
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PayrollRowKind {
+    Deposit,
+    Fee,
+    Payout,
+    Refund,
+    Tax,
+}
+
+/// One movement of funds in the payroll of a payer.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct PayrollRow {
+    pub invoice_id: u64,
+    pub kind: PayrollRowKind,
+    /// payee of the invoice for payouts, the escrow for deposits and refunds, the admin
+    /// wallet for fees and the tax authority for withheld tax
+    pub counterparty: String,
+    pub token: Token,
+    pub amount: Uint128,
+    pub block_height: u64,
+    pub time: u64,
+}
+
+// rows of each payer in the order they happened, so by block time
+pub static PAYROLL: AppendStore<PayrollRow, Json> = AppendStore::new(PREFIX_PAYROLL);
+
+pub struct PayrollStore {}
+
+impl PayrollStore {
+    pub fn push(store: &mut dyn Storage, payer: &str, row: &PayrollRow) -> StdResult<()> {
+        PAYROLL.add_suffix(payer.as_bytes()).push(store, row)
+    }
+
+    /// Page `page` of the rows of `payer` between `from` and `to`, and the number of rows
+    /// in that window. Only the window bounds and the page itself are read.
+    pub fn page(
+        store: &dyn Storage,
+        payer: &str,
+        from: u64,
+        to: u64,
+        page: u32,
+        page_size: u32,
+    ) -> StdResult<(Vec<PayrollRow>, u32)> {
+        let rows = PAYROLL.add_suffix(payer.as_bytes());
+
+        page_by_time(store, &rows, from, to, page, page_size, |row| row.time)
+    }
+}
+
+/// Position of the first of `items` after `time`, or at it with `inclusive`, for items
+/// kept in the order of their time.
+fn position_by_time<T: Serialize + DeserializeOwned>(
+    store: &dyn Storage,
+    items: &AppendStore<T, Json>,
+    time: u64,
+    inclusive: bool,
+    time_of: impl Fn(&T) -> u64,
+) -> StdResult<u32> {
+    let (mut low, mut high) = (0, items.get_len(store)?);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        let item_time = time_of(&items.get_at(store, middle)?);
+        if item_time < time || (!inclusive && item_time == time) {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+
+    Ok(high)
+}
+
+/// Page `page` of `items` between `from` and `to`, and the number of items in that window,
+/// for items kept in the order of their time. Only the window bounds and the page are read.
+fn page_by_time<T: Serialize + DeserializeOwned>(
+    store: &dyn Storage,
+    items: &AppendStore<T, Json>,
+    from: u64,
+    to: u64,
+    page: u32,
+    page_size: u32,
+    time_of: impl Fn(&T) -> u64 + Copy,
+) -> StdResult<(Vec<T>, u32)> {
+    let start = position_by_time(store, items, from, true, time_of)?;
+    let end = position_by_time(store, items, to, false, time_of)?.max(start);
+
+    let first = start.saturating_add(page.saturating_mul(page_size)).min(end);
+    let last = first.saturating_add(page_size).min(end);
+    let page = (first..last)
+        .map(|position| items.get_at(store, position))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok((page, end - start))
+}
+
+/// Addresses an invoice is stored under, to find it from its id alone.
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
+pub struct InvoiceParties {
+    pub payee: String,
+    pub payer: String,
+}
+
+impl InvoiceParties {
+    pub fn is_party(&self, address: &Addr) -> bool {
+        self.payee == address.as_str() || self.payer == address.as_str()
+    }
+}
+
+pub static INVOICE_PARTIES: Keymap<u64, InvoiceParties, Json> = Keymap::new(PREFIX_INVOICE_PARTIES);
+
+pub struct PartiesStore {}
+
+impl PartiesStore {
+    pub fn save(store: &mut dyn Storage, id: u64, parties: &InvoiceParties) -> StdResult<()> {
+        INVOICE_PARTIES.insert(store, &id, parties)
+    }
+
+    pub fn load(store: &dyn Storage, id: u64) -> StdResult<InvoiceParties> {
+        Self::may_load(store, id)
+            .ok_or_else(|| StdError::generic_err(format!("Invoice {} not found", id)))
+    }
+
+    pub fn may_load(store: &dyn Storage, id: u64) -> Option<InvoiceParties> {
+        INVOICE_PARTIES.get(store, &id)
+    }
+
+    /// Parties of invoice `id`, signed for by `sender`. Invoices stored before the parties
+    /// were kept are found under `sender` as their payer or their payee instead.
+    pub fn load_for(store: &dyn Storage, id: u64, sender: &Addr) -> StdResult<InvoiceParties> {
+        if let Some(parties) = Self::may_load(store, id) {
+            return Ok(parties);
+        }
+
+        let invoice = match CONTRACT.add_suffix(sender.as_bytes()).get(store, &id) {
+            Some(contract) => Some(contract.invoice),
+            None => INVOICE.add_suffix(sender.as_bytes()).get(store, &id),
+        };
+
+        match invoice {
+            Some(invoice) => Ok(InvoiceParties {
+                payee: invoice.receiver,
+                payer: invoice.payer,
+            }),
+            None => Err(StdError::generic_err(format!("Invoice {} not found", id))),
+        }
+    }
+
+    pub fn remove(store: &mut dyn Storage, id: u64) -> StdResult<()> {
+        INVOICE_PARTIES.remove(store, &id)
+    }
+}
+
+// expiry time of the invoices waiting for acceptance
+pub static EXPIRY: Keymap<u64, u64, Json> = Keymap::new(PREFIX_EXPIRY);
+
+pub struct ExpiryStore {}
+
+impl ExpiryStore {
+    // invoices by expiry time, soonest first
+    fn by_time() -> SortedIndex {
+        SortedIndex::new(PREFIX_EXPIRY.to_vec())
+    }
+
+    pub fn save(store: &mut dyn Storage, id: u64, expires_at: u64) -> StdResult<()> {
+        Self::remove(store, id)?;
+        Self::by_time().insert(
+            store,
+            IndexEntry {
+                key: u128::from(expires_at),
+                id,
+            },
+        )?;
+
+        EXPIRY.insert(store, &id, &expires_at)
+    }
+
+    pub fn remove(store: &mut dyn Storage, id: u64) -> StdResult<()> {
+        if let Some(expires_at) = EXPIRY.get(store, &id) {
+            Self::by_time().remove(
+                store,
+                IndexEntry {
+                    key: u128::from(expires_at),
+                    id,
+                },
+            )?;
+            EXPIRY.remove(store, &id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Ids of up to `limit` invoices expired at `now`, reading no further
+    /// than the first one still valid.
+    pub fn expired(store: &dyn Storage, now: u64, limit: usize) -> StdResult<Vec<u64>> {
+        let mut ids = vec![];
+        Self::by_time().visit(store, None, false, |entry| {
+            if ids.len() == limit || entry.key > u128::from(now) {
+                return Ok(false);
+            }
+
+            ids.push(entry.id);
+            Ok(true)
+        })?;
+
+        Ok(ids)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum InvoiceEventKind {
+    Submitted,
+    Accepted,
+    Deposited,
+    Withdrawn,
+    Cancelled,
+    Refunded,
+    PenaltyPaid,
+    MilestoneSubmitted,
+    MilestoneApproved,
+    DisputeRaised,
+    DisputeResolved,
+    AmendmentProposed,
+    AmendmentAccepted,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
+pub struct InvoiceEvent {
+    pub kind: InvoiceEventKind,
+    pub actor: String,
+    pub amount: Uint128,
+    pub fee: Uint128,
+    pub token: Token,
+    pub block_height: u64,
+    pub time: u64,
+    /// tax withheld from a payout and paid to the tax authority
+    #[serde(default)]
+    pub withheld: Uint128,
+}
+
+impl InvoiceEvent {
+    pub fn new(
+        kind: InvoiceEventKind,
+        actor: &Addr,
+        amount: Uint128,
+        fee: Uint128,
+        token: &Token,
+        env: &Env,
+    ) -> Self {
+        InvoiceEvent {
+            kind,
+            actor: actor.to_string(),
+            amount,
+            fee,
+            token: token.clone(),
+            block_height: env.block.height,
+            time: env.block.time.seconds(),
+            withheld: Uint128::zero(),
+        }
+    }
+
+    pub fn with_withheld(self, withheld: Uint128) -> Self {
+        InvoiceEvent { withheld, ..self }
+    }
+}
+
+pub static INVOICE_EVENT: AppendStore<InvoiceEvent, Json> = AppendStore::new(PREFIX_INVOICE_EVENT);
+
+pub struct HistoryStore {}
+
+impl HistoryStore {
+    pub fn push(store: &mut dyn Storage, id: u64, event: &InvoiceEvent) -> StdResult<()> {
+        INVOICE_EVENT
+            .add_suffix(&id.to_be_bytes())
+            .push(store, event)
+    }
+
+    pub fn paging_event_list(
+        store: &dyn Storage,
+        id: u64,
+        page: u32,
+        page_size: u32,
+    ) -> StdResult<Vec<InvoiceEvent>> {
+        INVOICE_EVENT
+            .add_suffix(&id.to_be_bytes())
+            .paging(store, page, page_size)
+    }
+
+    pub fn num_event(store: &dyn Storage, id: u64) -> u32 {
+        INVOICE_EVENT
+            .add_suffix(&id.to_be_bytes())
+            .get_len(store)
+            .unwrap_or(0)
+    }
+
+    pub fn remove(store: &mut dyn Storage, id: u64) -> StdResult<()> {
+        let events = INVOICE_EVENT.add_suffix(&id.to_be_bytes());
+        for _ in 0..events.get_len(store)? {
+            events.pop(store)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
+pub struct DisputeResolution {
+    pub arbiter: String,
+    pub payee_share: Uint128,
+    pub payer_share: Uint128,
+    pub resolved_at: u64,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
+pub struct Dispute {
+    pub raised_by: String,
+    pub reason: String,
+    pub raised_at: u64,
+    pub resolution: Option<DisputeResolution>,
+}
+
+// resolved disputes of each invoice
+pub static DISPUTE: AppendStore<Dispute, Json> = AppendStore::new(PREFIX_DISPUTE);
+
+pub struct DisputeStore {}
+
+impl DisputeStore {
+    pub fn push(store: &mut dyn Storage, id: u64, dispute: &Dispute) -> StdResult<()> {
+        DISPUTE.add_suffix(&id.to_be_bytes()).push(store, dispute)
+    }
+
+    pub fn load_all(store: &dyn Storage, id: u64) -> StdResult<Vec<Dispute>> {
+        let disputes = DISPUTE.add_suffix(&id.to_be_bytes());
+        let len = disputes.get_len(store)?;
+        if len == 0 {
+            return Ok(vec![]);
+        }
+
+        disputes.paging(store, 0, len)
+    }
+}
+
+// balance each payer keeps in the contract to fund their invoices
+pub static TREASURY: Keymap<String, Uint128, Json> = Keymap::new(PREFIX_TREASURY);
+
+pub struct TreasuryStore {}
+
+impl TreasuryStore {
+    pub fn load(store: &dyn Storage, payer: &Addr) -> Uint128 {
+        TREASURY
+            .get(store, &payer.to_string())
+            .unwrap_or_default()
+    }
+
+    /// Treasury balance a contract settled in `denom` can draw from.
+    pub fn available(store: &dyn Storage, payer: &Addr, denom: &str) -> Uint128 {
+        match denom == TREASURY_DENOM {
+            true => Self::load(store, payer),
+            false => Uint128::zero(),
+        }
+    }
+
+    /// Treasury balance the payments of `invoice` can draw from.
+    pub fn covering(store: &dyn Storage, payer: &Addr, invoice: &Invoice) -> Uint128 {
+        match &invoice.token {
+            Token::Native(denom) => Self::available(store, payer, denom),
+            Token::Snip20(_) => Uint128::zero(),
+        }
+    }
+
+    pub fn save(store: &mut dyn Storage, payer: &Addr, balance: Uint128) -> StdResult<()> {
+        TREASURY.insert(store, &payer.to_string(), &balance)
+    }
+}
+
+/// Payer action an operator can be allowed to take.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OperatorPermission {
+    AcceptInvoice,
+    TopUp,
+    CancelPayment,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Operator {
+    pub permissions: Vec<OperatorPermission>,
+    /// time the grant ends at, if any
+    pub expires: Option<u64>,
+}
+
+// operators each payer allows to act on their behalf
+pub static OPERATOR: Keymap<String, Operator, Json> = Keymap::new(PREFIX_OPERATOR);
+
+pub struct OperatorStore {}
+
+impl OperatorStore {
+    pub fn save(
+        store: &mut dyn Storage,
+        payer: &Addr,
+        operator: &Addr,
+        grant: &Operator,
+    ) -> StdResult<()> {
+        OPERATOR
+            .add_suffix(payer.as_bytes())
+            .insert(store, &operator.to_string(), grant)
+    }
+
+    pub fn remove(store: &mut dyn Storage, payer: &Addr, operator: &Addr) -> StdResult<bool> {
+        let operators = OPERATOR.add_suffix(payer.as_bytes());
+        if !operators.contains(store, &operator.to_string()) {
+            return Ok(false);
+        }
+
+        operators.remove(store, &operator.to_string())?;
+        Ok(true)
+    }
+
+    /// Whether `operator` may take `permission` for `payer` at `now`.
+    pub fn is_authorized(
+        store: &dyn Storage,
+        payer: &Addr,
+        operator: &Addr,
+        permission: OperatorPermission,
+        now: u64,
+    ) -> bool {
+        match OPERATOR
+            .add_suffix(payer.as_bytes())
+            .get(store, &operator.to_string())
+        {
+            Some(grant) => {
+                let expired = matches!(grant.expires, Some(expires) if now >= expires);
+                grant.permissions.contains(&permission) && !expired
+            }
+            None => false,
+        }
+    }
+}
+
+/// Team running payroll together: the owner and admins pay the invoices
+/// billed to it, submitted by its members.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Organization {
+    pub id: u64,
+    pub name: String,
+    pub owner: String,
+    pub admins: Vec<String>,
+    pub members: Vec<String>,
+}
+
+impl Organization {
+    pub fn is_admin(&self, address: &Addr) -> bool {
+        self.owner == address.as_str() || self.admins.iter().any(|admin| admin == address.as_str())
+    }
+
+    pub fn is_member(&self, address: &Addr) -> bool {
+        self.is_admin(address) || self.members.iter().any(|member| member == address.as_str())
+    }
+}
+
+pub static ORGANIZATION: Keymap<u64, Organization, Json> = Keymap::new(PREFIX_ORGANIZATION);
+
+// invoices billed to each organization
+pub static ORGANIZATION_INVOICE: Keymap<u64, bool, Json> =
+    Keymap::new(PREFIX_ORGANIZATION_INVOICE);
+
+pub struct OrganizationStore {}
+
+impl OrganizationStore {
+    pub fn save(store: &mut dyn Storage, organization: &Organization) -> StdResult<()> {
+        ORGANIZATION.insert(store, &organization.id, organization)
+    }
+
+    pub fn load(store: &dyn Storage, id: u64) -> StdResult<Organization> {
+        ORGANIZATION
+            .get(store, &id)
+            .ok_or_else(|| StdError::generic_err(format!("Organization {} not found", id)))
+    }
+
+    pub fn add_invoice(store: &mut dyn Storage, id: u64, invoice_id: u64) -> StdResult<()> {
+        ORGANIZATION_INVOICE
+            .add_suffix(&id.to_be_bytes())
+            .insert(store, &invoice_id, &true)
+    }
+
+    pub fn remove_invoice(store: &mut dyn Storage, id: u64, invoice_id: u64) -> StdResult<()> {
+        let invoices = ORGANIZATION_INVOICE.add_suffix(&id.to_be_bytes());
+        if invoices.contains(store, &invoice_id) {
+            invoices.remove(store, &invoice_id)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn paging_invoice_ids(
+        store: &dyn Storage,
+        id: u64,
+        page: u32,
+        page_size: u32,
+    ) -> StdResult<Vec<u64>> {
+        Ok(ORGANIZATION_INVOICE
+            .add_suffix(&id.to_be_bytes())
+            .paging(store, page, page_size)?
+            .into_iter()
+            .map(|(invoice_id, _)| invoice_id)
+            .collect())
+    }
+
+    pub fn num_invoices(store: &dyn Storage, id: u64) -> u32 {
+        ORGANIZATION_INVOICE
+            .add_suffix(&id.to_be_bytes())
+            .get_len(store)
+            .unwrap_or(0)
+    }
+}
+
+/// Label the payer puts on an invoice to break their spend down, such as a
+/// cost center, project code or department.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Tag {
+    pub key: String,
+    pub value: String,
+}
+
+// tags of each invoice, kept apart from the invoice as only the payer can see them
+pub static TAG: Keymap<u64, Vec<Tag>, Json> = Keymap::new(PREFIX_TAG);
+
+pub struct TagStore {}
+
+impl TagStore {
+    pub fn save(store: &mut dyn Storage, id: u64, tags: &[Tag]) -> StdResult<()> {
+        if tags.is_empty() {
+            return Self::remove(store, id);
+        }
+
+        TAG.insert(store, &id, &tags.to_vec())
+    }
+
+    pub fn load(store: &dyn Storage, id: u64) -> Vec<Tag> {
+        TAG.get(store, &id).unwrap_or_default()
+    }
+
+    pub fn remove(store: &mut dyn Storage, id: u64) -> StdResult<()> {
+        if TAG.contains(store, &id) {
+            TAG.remove(store, &id)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Spend of a payer on the invoices carrying a tag, in one token.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct TagSpend {
+    pub token: Token,
+    /// deposited into escrow
+    pub escrowed: Uint128,
+    /// paid out to the payees, penalties included
+    pub paid: Uint128,
+    pub refunded: Uint128,
+}
+
+/// Spend of a payer on a tag, from the first event counted up to the one at `time`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct TagSpendTotal {
+    pub time: u64,
+    pub totals: Vec<TagSpend>,
+}
+
+// running spend of each payer on each tag, in the order of the events, so by block time
+pub static TAG_SPEND: AppendStore<TagSpendTotal, Json> = AppendStore::new(PREFIX_TAG_SPEND);
+
+pub struct TagSpendStore {}
+
+impl TagSpendStore {
+    fn totals(payer: &str, tag: &Tag) -> StdResult<AppendStore<'static, TagSpendTotal, Json>> {
+        Ok(TAG_SPEND
+            .add_suffix(payer.as_bytes())
+            .add_suffix(&to_vec(tag)?))
+    }
+
+    /// Counts `spend`, moved at `time`, in the running spend of `payer` on `tag`.
+    pub fn add(
+        store: &mut dyn Storage,
+        payer: &str,
+        tag: &Tag,
+        time: u64,
+        spend: &TagSpend,
+    ) -> StdResult<()> {
+        let totals = Self::totals(payer, tag)?;
+        let mut running = match totals.get_len(store)? {
+            0 => TagSpendTotal {
+                time,
+                totals: vec![],
+            },
+            len => totals.get_at(store, len - 1)?,
+        };
+        running.time = time;
+
+        let spends = &mut running.totals;
+        match spends.iter_mut().find(|total| total.token == spend.token) {
+            Some(total) => {
+                total.escrowed += spend.escrowed;
+                total.paid += spend.paid;
+                total.refunded += spend.refunded;
+            }
+            None => spends.push(spend.clone()),
+        }
+
+        totals.push(store, &running)
+    }
+
+    /// Spend of `payer` on `tag` between `from` and `to`. Only the running spend at the
+    /// window bounds is read.
+    pub fn between(
+        store: &dyn Storage,
+        payer: &str,
+        tag: &Tag,
+        from: u64,
+        to: u64,
+    ) -> StdResult<Vec<TagSpend>> {
+        let totals = Self::totals(payer, tag)?;
+
+        // running spend before the first event after `time`, or at it with `inclusive`
+        let total_before = |time: u64, inclusive: bool| -> StdResult<Vec<TagSpend>> {
+            match position_by_time(store, &totals, time, inclusive, |total| total.time)? {
+                0 => Ok(vec![]),
+                position => Ok(totals.get_at(store, position - 1)?.totals),
+            }
+        };
+        let before = total_before(from, true)?;
+
+        let spends = total_before(to, false)?
+            .into_iter()
+            .map(|mut spend| {
+                if let Some(earlier) = before.iter().find(|earlier| earlier.token == spend.token) {
+                    spend.escrowed = spend.escrowed.saturating_sub(earlier.escrowed);
+                    spend.paid = spend.paid.saturating_sub(earlier.paid);
+                    spend.refunded = spend.refunded.saturating_sub(earlier.refunded);
+                }
+
+                spend
+            })
+            .filter(|spend| !(spend.escrowed + spend.paid + spend.refunded).is_zero())
+            .collect();
+
+        Ok(spends)
+    }
+}
+
+// private notes between the parties of each invoice, kept apart from the invoice
+pub static MEMO: Keymap<u64, String, Json> = Keymap::new(PREFIX_MEMO);
+
+pub struct MemoStore {}
+
+impl MemoStore {
+    pub fn save(store: &mut dyn Storage, id: u64, memo: &str) -> StdResult<()> {
+        MEMO.insert(store, &id, &memo.to_string())
+    }
+
+    pub fn load(store: &dyn Storage, id: u64) -> Option<String> {
+        MEMO.get(store, &id)
+    }
+
+    pub fn remove(store: &mut dyn Storage, id: u64) -> StdResult<()> {
+        if MEMO.contains(store, &id) {
+            MEMO.remove(store, &id)?;
+        }
+
+        Ok(())
+    }
+}
+
+// breakdown of the amount of each invoice, as private as its memo
+pub static LINE_ITEMS: Keymap<u64, Vec<LineItem>, Json> = Keymap::new(PREFIX_LINE_ITEMS);
+
+pub struct LineItemStore {}
+
+impl LineItemStore {
+    pub fn save(store: &mut dyn Storage, id: u64, line_items: &[LineItem]) -> StdResult<()> {
+        LINE_ITEMS.insert(store, &id, &line_items.to_vec())
+    }
+
+    pub fn load(store: &dyn Storage, id: u64) -> Vec<LineItem> {
+        LINE_ITEMS.get(store, &id).unwrap_or_default()
+    }
+
+    pub fn remove(store: &mut dyn Storage, id: u64) -> StdResult<()> {
+        if LINE_ITEMS.contains(store, &id) {
+            LINE_ITEMS.remove(store, &id)?;
+        }
+
+        Ok(())
+    }
+}